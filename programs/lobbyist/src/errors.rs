@@ -28,4 +28,86 @@ pub enum LobbyistError {
     NotEnoughTimePassed,
     #[msg("Error: Invalid oracle aggregator")]
     InvalidOracleAggregator,
+    #[msg("Error: Invalid conditional mint")]
+    InvalidConditionalMint,
+    // 10
+    #[msg("Error: Invalid conditional vault")]
+    InvalidConditionalVault,
+    #[msg("Error: Invalid question")]
+    InvalidQuestion,
+    #[msg("Error: Decision markets are not live")]
+    MarketsNotLive,
+    #[msg("Error: Math overflow")]
+    MathOverflow,
+    #[msg("Error: Invalid cranker fee")]
+    InvalidCrankerFee,
+    // 15
+    #[msg("Error: Invalid maximum slippage")]
+    InvalidMaxSlippage,
+    #[msg("Error: Slippage exceeded")]
+    SlippageExceeded,
+    #[msg("Error: Invalid trade window")]
+    InvalidTradeWindow,
+    #[msg("Error: Trade window budget exhausted")]
+    TradeWindowExhausted,
+    #[msg("Error: Proposal is not finalized")]
+    ProposalNotFinalized,
+    // 20
+    #[msg("Error: Escrow is already settled")]
+    EscrowAlreadySettled,
+    #[msg("Error: Invalid spot target")]
+    InvalidSpotTarget,
+    #[msg("Error: Oracle is stale")]
+    StaleOracle,
+    #[msg("Error: Invalid strategy")]
+    InvalidStrategy,
+    #[msg("Error: Invalid market making band")]
+    InvalidMarketMakingBand,
+    // 25
+    #[msg("Error: Invalid remaining accounts")]
+    InvalidRemainingAccounts,
+    #[msg("Error: Invalid escrow token account")]
+    InvalidEscrowTokenAccount,
+    #[msg("Error: Escrows cannot be crossed")]
+    EscrowsNotCrossable,
+    #[msg("Error: Proposal is not pending")]
+    ProposalNotPending,
+    #[msg("Error: Proposal trading ended")]
+    ProposalTradingEnded,
+    // 30
+    #[msg("Error: Proposal is finalized")]
+    ProposalFinalized,
+    #[msg("Error: Invalid price source")]
+    InvalidPriceSource,
+    #[msg("Error: Invalid bullish threshold")]
+    InvalidBullishThreshold,
+    #[msg("Error: Invalid bearish threshold")]
+    InvalidBearishThreshold,
+    #[msg("Error: Strategy threshold is not set")]
+    MissingStrategyThreshold,
+    // 35
+    #[msg("Error: Strategy does not use thresholds")]
+    UnusedStrategyThreshold,
+    #[msg("Error: Invalid escrow status")]
+    InvalidEscrowStatus,
+    #[msg("Error: Escrow is not active")]
+    EscrowNotActive,
+    #[msg("Error: Escrow is already active")]
+    EscrowAlreadyActive,
+    #[msg("Error: Escrow is already paused")]
+    EscrowAlreadyPaused,
+    // 40
+    #[msg("Error: Escrow is not paused")]
+    EscrowNotPaused,
+    #[msg("Error: Escrow is only done once settled")]
+    StatusRequiresSettlement,
+    #[msg("Error: Invalid preferences delay")]
+    InvalidPreferencesDelay,
+    #[msg("Error: Escrow is not empty")]
+    EscrowNotEmpty,
+    #[msg("Error: Tracked amount exceeds the token account balance")]
+    TrackedAmountExceedsBalance,
+    // 45
+    #[msg("Error: Withdrawal exceeds the tracked amount")]
+    WithdrawalExceedsTrackedAmount,
 }
@@ -0,0 +1,173 @@
+use {
+    crate::{
+        errors::LobbyistError,
+        futarchy_cpi::Proposal,
+        state::{Escrow, EscrowStatus},
+    },
+    typhoon::prelude::*,
+    typhoon_token::{
+        spl_instructions::{Burn, CloseAccount, TransferChecked},
+        Mint, TokenAccount, TokenProgram,
+    },
+};
+
+#[context]
+pub struct CloseEscrow {
+    pub depositor: Mut<Signer>,
+    #[constraint(
+        has_one = pass_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = pass_quote_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_quote_mint @ LobbyistError::InvalidConditionalMint,
+    )]
+    pub proposal: BorshAccount<Proposal>,
+    #[constraint(
+        seeded,
+        bump = escrow.data_unchecked()?.bump,
+        has_one = depositor @ LobbyistError::InvalidDepositor,
+        has_one = proposal @ LobbyistError::InvalidProposal,
+        has_one = base_mint @ LobbyistError::InvalidBaseMint,
+        has_one = quote_mint @ LobbyistError::InvalidQuoteMint,
+    )]
+    pub escrow: Mut<Account<Escrow>>,
+    pub base_mint: Account<Mint>,
+    pub quote_mint: Account<Mint>,
+    pub pass_base_mint: Mut<Account<Mint>>,
+    pub pass_quote_mint: Mut<Account<Mint>>,
+    pub fail_base_mint: Mut<Account<Mint>>,
+    pub fail_quote_mint: Mut<Account<Mint>>,
+    #[constraint(
+        associated_token::mint = base_mint,
+        associated_token::authority = depositor,
+    )]
+    pub user_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = quote_mint,
+        associated_token::authority = depositor,
+    )]
+    pub user_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_quote_ata: Mut<Account<TokenAccount>>,
+    pub token_program: Program<TokenProgram>,
+}
+
+/// Closes the escrow token accounts and the escrow once emptied, returning the rent to the
+/// depositor.
+///
+/// Settled escrows may still hold dust, the spot dust is swept to the depositor and the
+/// conditional dust burned before the accounts are closed.
+pub fn close_escrow(ctx: CloseEscrow) -> ProgramResult {
+    msg!("Close escrow");
+
+    let escrow = *ctx.escrow.data()?;
+    match EscrowStatus::try_from(escrow.status)? {
+        EscrowStatus::Active => return Err(LobbyistError::EscrowNotPaused.into()),
+        EscrowStatus::Paused => {
+            if [
+                escrow.base_amount,
+                escrow.quote_amount,
+                escrow.pass_base_amount,
+                escrow.pass_quote_amount,
+                escrow.fail_base_amount,
+                escrow.fail_quote_amount,
+            ] != [0; 6]
+            {
+                return Err(LobbyistError::EscrowNotEmpty.into());
+            }
+        }
+        EscrowStatus::Done => {}
+    }
+
+    let bump = [escrow.bump];
+    let seeds = Escrow::derive_signer_seeds_with_bump(&escrow.depositor, &escrow.proposal, &bump);
+
+    for (from, mint, to) in [
+        (&ctx.escrow_base_ata, &ctx.base_mint, &ctx.user_base_ata),
+        (&ctx.escrow_quote_ata, &ctx.quote_mint, &ctx.user_quote_ata),
+    ] {
+        let amount = from.data()?.amount();
+        if amount == 0 {
+            continue;
+        }
+
+        TransferChecked {
+            from: from.as_ref(),
+            mint: mint.as_ref(),
+            to: to.as_ref(),
+            authority: ctx.escrow.as_ref(),
+            amount,
+            decimals: mint.data()?.decimals(),
+        }
+        .invoke_signed(&[instruction::CpiSigner::from(&seeds)])?;
+    }
+
+    for (account, mint) in [
+        (&ctx.escrow_pass_base_ata, &ctx.pass_base_mint),
+        (&ctx.escrow_pass_quote_ata, &ctx.pass_quote_mint),
+        (&ctx.escrow_fail_base_ata, &ctx.fail_base_mint),
+        (&ctx.escrow_fail_quote_ata, &ctx.fail_quote_mint),
+    ] {
+        let amount = account.data()?.amount();
+        if amount == 0 {
+            continue;
+        }
+
+        Burn {
+            account: account.as_ref(),
+            mint: mint.as_ref(),
+            authority: ctx.escrow.as_ref(),
+            amount,
+        }
+        .invoke_signed(&[instruction::CpiSigner::from(&seeds)])?;
+    }
+
+    for account in [
+        &ctx.escrow_base_ata,
+        &ctx.escrow_quote_ata,
+        &ctx.escrow_pass_base_ata,
+        &ctx.escrow_pass_quote_ata,
+        &ctx.escrow_fail_base_ata,
+        &ctx.escrow_fail_quote_ata,
+    ] {
+        CloseAccount {
+            account: account.as_ref(),
+            destination: ctx.depositor.as_ref(),
+            authority: ctx.escrow.as_ref(),
+        }
+        .invoke_signed(&[instruction::CpiSigner::from(&seeds)])?;
+    }
+
+    let escrow_info = ctx.escrow.as_ref();
+    *ctx.depositor.as_ref().try_borrow_mut_lamports()? += escrow_info.lamports();
+    escrow_info.close()?;
+
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use {
+    super::{
+        trade::{
+            apply_preferences, execute_swap, load_dao, market_pool, pay_cranker_fee, plan_trade,
+            quote_swap, split_input, store_snapshots, EscrowAccounts, PlannedSwap, Trade,
+        },
+        trade_batch::{EscrowGroup, BATCH_ESCROW_ACCOUNTS},
+    },
+    crate::{
+        errors::LobbyistError,
+        futarchy_cpi::{ConditionalSwapParams, Market, SwapType},
+        state::Escrow,
+        utils::{reserves_price, PRICE_SCALE},
+    },
+    typhoon::prelude::*,
+    typhoon_token::spl_instructions::TransferChecked,
+};
+
+/// Matches the opposing swaps of the context escrow and of the escrow listed in the remaining
+/// accounts at the pool mid price, moving the conditional tokens directly between them.
+///
+/// Only the residual of the larger swap is sent to the AMM, its minimum output derived from the
+/// mid price.
+pub fn cross_escrows(ctx: Trade, remaining_accounts: Remaining) -> ProgramResult {
+    msg!("Cross escrows");
+
+    if remaining_accounts.len() != BATCH_ESCROW_ACCOUNTS {
+        return Err(LobbyistError::InvalidRemainingAccounts.into());
+    }
+    let group = EscrowGroup::load(&ctx, &remaining_accounts)?;
+    if group.accounts().escrow.key() == ctx.escrow.key() {
+        return Err(LobbyistError::EscrowsNotCrossable.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    let first = EscrowAccounts::from_context(&ctx);
+    let second = group.accounts();
+
+    apply_preferences(&first, now)?;
+    apply_preferences(&second, now)?;
+    let first_plan = plan_trade(&ctx, &first.escrow.data()?, now)?;
+    let second_plan = plan_trade(&ctx, &second.escrow.data()?, now)?;
+    store_snapshots(&first, &first_plan.snapshots)?;
+    store_snapshots(&second, &second_plan.snapshots)?;
+
+    let (Some(first_swap), Some(second_swap)) = (first_plan.swap, second_plan.swap) else {
+        return Err(LobbyistError::EscrowsNotCrossable.into());
+    };
+    let (buyer, buy, seller, sell) =
+        match (&first_swap.params.swap_type, &second_swap.params.swap_type) {
+            (SwapType::Buy, SwapType::Sell) => (&first, first_swap, &second, second_swap),
+            (SwapType::Sell, SwapType::Buy) => (&second, second_swap, &first, first_swap),
+            _ => return Err(LobbyistError::EscrowsNotCrossable.into()),
+        };
+    let market = buy.params.market;
+    if !matches!(
+        (&market, &sell.params.market),
+        (Market::Spot, Market::Spot) | (Market::Pass, Market::Pass) | (Market::Fail, Market::Fail)
+    ) {
+        return Err(LobbyistError::EscrowsNotCrossable.into());
+    }
+
+    // Sized before any transfer, the residual swaps move the pool
+    let (quote_amount, base_amount, buy_residual, sell_residual) = {
+        let dao = load_dao(&ctx)?;
+        let pool = market_pool(&dao.amm.state, &market).ok_or(LobbyistError::MarketsNotLive)?;
+        let price = reserves_price(pool.base_reserves, pool.quote_reserves)
+            .ok_or(LobbyistError::MathOverflow)?;
+
+        let sell_value = (sell.params.input_amount as u128)
+            .checked_mul(price)
+            .ok_or(LobbyistError::MathOverflow)?
+            / PRICE_SCALE;
+        let quote_amount = (buy.params.input_amount as u128).min(sell_value) as u64;
+        let base_amount = (quote_amount as u128 * PRICE_SCALE / price) as u64;
+        msg!(format!("Crossing {} base for {} quote", base_amount, quote_amount).as_str());
+
+        let buy_residual = match buy.params.input_amount - quote_amount {
+            0 => None,
+            amount => Some(quote_swap(
+                &buyer.escrow.data()?,
+                market.clone(),
+                pool,
+                SwapType::Buy,
+                amount,
+                price,
+            )?),
+        };
+        let sell_residual = match sell.params.input_amount - base_amount {
+            0 => None,
+            amount => Some(quote_swap(
+                &seller.escrow.data()?,
+                market.clone(),
+                pool,
+                SwapType::Sell,
+                amount,
+                price,
+            )?),
+        };
+
+        (quote_amount, base_amount, buy_residual, sell_residual)
+    };
+
+    if quote_amount > 0 && base_amount > 0 {
+        cross(&ctx, buyer, seller, &market, base_amount, quote_amount, now)?;
+    }
+
+    for (accounts, residual) in [(buyer, buy_residual), (seller, sell_residual)] {
+        if let Some(PlannedSwap { params, .. }) = residual {
+            execute_swap(&ctx, accounts, params, now)?;
+        }
+    }
+
+    first.check_backing()?;
+    second.check_backing()
+}
+
+/// Pays the cranker for both sides, exchanges conditional tokens between the escrows and books
+/// the trade on both sides
+fn cross(
+    ctx: &Trade,
+    buyer: &EscrowAccounts,
+    seller: &EscrowAccounts,
+    market: &Market,
+    base_amount: u64,
+    quote_amount: u64,
+    now: i64,
+) -> ProgramResult {
+    for (accounts, swap_type, input_amount) in [
+        (buyer, SwapType::Buy, quote_amount),
+        (seller, SwapType::Sell, base_amount),
+    ] {
+        let escrow = *accounts.escrow.data()?;
+        pay_cranker_fee(ctx, accounts, &escrow, &swap_type, input_amount)?;
+
+        let escrow = *accounts.escrow.data()?;
+        split_input(
+            ctx,
+            accounts,
+            &escrow,
+            &ConditionalSwapParams {
+                input_amount,
+                market: market.clone(),
+                swap_type,
+                min_output_amount: 0,
+            },
+        )?;
+    }
+
+    let (base_mint, quote_mint) = match market {
+        Market::Spot => (ctx.base_mint.as_ref(), ctx.quote_mint.as_ref()),
+        Market::Pass => (ctx.pass_base_mint.as_ref(), ctx.pass_quote_mint.as_ref()),
+        Market::Fail => (ctx.fail_base_mint.as_ref(), ctx.fail_quote_mint.as_ref()),
+    };
+    let (buyer_base_ata, buyer_quote_ata) = buyer.market_atas(market);
+    let (seller_base_ata, seller_quote_ata) = seller.market_atas(market);
+
+    // Conditional mints share the decimals of their underlying mint
+    for (from, to, authority, mint, amount, decimals) in [
+        (
+            buyer_quote_ata,
+            seller_quote_ata,
+            buyer,
+            quote_mint,
+            quote_amount,
+            ctx.quote_mint.data()?.decimals(),
+        ),
+        (
+            seller_base_ata,
+            buyer_base_ata,
+            seller,
+            base_mint,
+            base_amount,
+            ctx.base_mint.data()?.decimals(),
+        ),
+    ] {
+        let escrow = *authority.escrow.data()?;
+        let bump = [escrow.bump];
+        let seeds =
+            Escrow::derive_signer_seeds_with_bump(&escrow.depositor, &escrow.proposal, &bump);
+
+        TransferChecked {
+            from: from.as_ref(),
+            mint,
+            to: to.as_ref(),
+            authority: authority.escrow.as_ref(),
+            amount,
+            decimals,
+        }
+        .invoke_signed(&[instruction::CpiSigner::from(&seeds)])?;
+    }
+
+    {
+        let mut escrow = buyer.escrow.mut_data()?;
+        escrow.record_trade(now, quote_amount);
+        let (base, quote) = escrow.market_amounts_mut(market);
+        *quote -= quote_amount;
+        *base += base_amount;
+    }
+    {
+        let mut escrow = seller.escrow.mut_data()?;
+        escrow.record_trade(now, quote_amount);
+        let (base, quote) = escrow.market_amounts_mut(market);
+        *base -= base_amount;
+        *quote += quote_amount;
+    }
+
+    Ok(())
+}
@@ -1,5 +1,10 @@
 use {
-    crate::{errors::LobbyistError, state::Escrow, utils::PodU64},
+    crate::{
+        errors::LobbyistError,
+        futarchy_cpi::{Market, Proposal, ProposalState},
+        state::{Escrow, EscrowStatus},
+        utils::{trading_end_timestamp, PodU64},
+    },
     bytemuck::{AnyBitPattern, NoUninit},
     typhoon::prelude::*,
     typhoon_token::{spl_instructions::TransferChecked, Mint, TokenAccount, TokenProgram},
@@ -16,10 +21,12 @@ pub struct DepositArgs {
 #[args(DepositArgs)]
 pub struct Deposit {
     pub depositor: Mut<Signer>,
+    pub proposal: BorshAccount<Proposal>,
     #[constraint(
         seeded,
         bump = escrow.data_unchecked()?.bump,
         has_one = depositor @ LobbyistError::InvalidDepositor,
+        has_one = proposal @ LobbyistError::InvalidProposal,
         has_one = base_mint @ LobbyistError::InvalidBaseMint,
         has_one = quote_mint @ LobbyistError::InvalidQuoteMint,
     )]
@@ -53,6 +60,23 @@ pub struct Deposit {
 pub fn deposit(ctx: Deposit) -> ProgramResult {
     msg!("Deposit");
 
+    if ctx.escrow.data()?.status == EscrowStatus::Done as u8 {
+        return Err(LobbyistError::EscrowAlreadySettled.into());
+    }
+
+    let proposal = ctx.proposal.data()?;
+    match proposal.state {
+        ProposalState::Passed | ProposalState::Failed => {
+            return Err(LobbyistError::ProposalFinalized.into());
+        }
+        ProposalState::Pending
+            if Clock::get()?.unix_timestamp >= trading_end_timestamp(&proposal) =>
+        {
+            return Err(LobbyistError::ProposalTradingEnded.into());
+        }
+        _ => {}
+    }
+
     TransferChecked {
         from: ctx.user_base_ata.as_ref(),
         mint: ctx.base_mint.as_ref(),
@@ -76,6 +100,11 @@ pub fn deposit(ctx: Deposit) -> ProgramResult {
     let mut escrow = ctx.escrow.mut_data()?;
     escrow.base_amount += ctx.args.base_amount;
     escrow.quote_amount += ctx.args.quote_amount;
+    escrow.check_backing(
+        &Market::Spot,
+        ctx.escrow_base_ata.data()?.amount(),
+        ctx.escrow_quote_ata.data()?.amount(),
+    )?;
 
     Ok(())
 }
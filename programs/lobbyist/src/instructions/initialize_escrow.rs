@@ -2,20 +2,34 @@ use {
     crate::{
         errors::LobbyistError,
         futarchy_cpi::{Dao, Proposal},
-        state::Escrow,
-        PodI16,
+        state::{Escrow, EscrowPriceSource, EscrowStatus, EscrowStrategy},
+        utils::{PodI16, PodU128, PodU16, PodU32, PodU64, BPS_DENOMINATOR},
     },
     bytemuck::{AnyBitPattern, NoUninit},
     typhoon::prelude::*,
     typhoon_token::{ata_instructions::CreateIdempotent, AtaTokenProgram, Mint, TokenProgram},
 };
 
+pub const MAX_CRANKER_FEE_BPS: u16 = 100; // 1%
+pub const MAX_THRESHOLD_BPS: i16 = 20_000; // 200%
+pub const MIN_PREFERENCES_DELAY_SECONDS: u32 = 600; // 10 minutes
+
 #[derive(Debug, PartialEq, AnyBitPattern, NoUninit, Copy, Clone)]
 #[repr(C)]
 pub struct InitializeEscrowArgs {
     pub bullish_threshold_bps: PodI16,
     pub bearish_threshold_bps: PodI16,
-    pub bullish: u8,
+    pub strategy: u8,
+    pub cranker_fee_bps: PodU16,
+    pub max_slippage_bps: PodU16,
+    pub max_trade_per_window: PodU64,
+    pub trade_window_seconds: PodU32,
+    pub max_oracle_age_seconds: PodU32,
+    pub trade_cutoff_seconds: PodU32,
+    pub spot_target_base_bps: PodU16,
+    pub market_making_band_bps: PodU16,
+    pub price_source: u8,
+    pub preferences_delay_seconds: PodU32,
 }
 
 #[context]
@@ -31,6 +45,10 @@ pub struct InitializeEscrow {
     pub dao: BorshAccount<Dao>,
     #[constraint(
         has_one = dao @ LobbyistError::InvalidDao,
+        has_one = pass_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = pass_quote_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_quote_mint @ LobbyistError::InvalidConditionalMint,
     )]
     pub proposal: BorshAccount<Proposal>,
     #[constraint(
@@ -46,8 +64,16 @@ pub struct InitializeEscrow {
     pub escrow: Mut<Account<Escrow>>,
     pub base_mint: Account<Mint>,
     pub quote_mint: Account<Mint>,
+    pub pass_base_mint: Account<Mint>,
+    pub pass_quote_mint: Account<Mint>,
+    pub fail_base_mint: Account<Mint>,
+    pub fail_quote_mint: Account<Mint>,
     pub escrow_base_ata: Mut<UncheckedAccount>,
     pub escrow_quote_ata: Mut<UncheckedAccount>,
+    pub escrow_pass_base_ata: Mut<UncheckedAccount>,
+    pub escrow_pass_quote_ata: Mut<UncheckedAccount>,
+    pub escrow_fail_base_ata: Mut<UncheckedAccount>,
+    pub escrow_fail_quote_ata: Mut<UncheckedAccount>,
     pub token_program: Program<TokenProgram>,
     pub ata_token_program: Program<AtaTokenProgram>,
     pub system_program: Program<System>,
@@ -56,33 +82,78 @@ pub struct InitializeEscrow {
 pub fn initialize_escrow(ctx: InitializeEscrow) -> ProgramResult {
     msg!("Initialize escrow");
 
-    CreateIdempotent {
-        funding_account: ctx.depositor.as_ref(),
-        account: ctx.escrow_base_ata.as_ref(),
-        wallet: ctx.escrow.as_ref(),
-        mint: ctx.base_mint.as_ref(),
-        token_program: ctx.token_program.as_ref(),
-        system_program: ctx.system_program.as_ref(),
+    let cranker_fee_bps: u16 = ctx.args.cranker_fee_bps.into();
+    if cranker_fee_bps > MAX_CRANKER_FEE_BPS {
+        return Err(LobbyistError::InvalidCrankerFee.into());
+    }
+
+    let max_slippage_bps: u16 = ctx.args.max_slippage_bps.into();
+    if max_slippage_bps as u128 > BPS_DENOMINATOR {
+        return Err(LobbyistError::InvalidMaxSlippage.into());
+    }
+
+    let max_trade_per_window: u64 = ctx.args.max_trade_per_window.into();
+    let trade_window_seconds: u32 = ctx.args.trade_window_seconds.into();
+    if max_trade_per_window != 0 && trade_window_seconds == 0 {
+        return Err(LobbyistError::InvalidTradeWindow.into());
     }
-    .invoke()?;
-
-    CreateIdempotent {
-        funding_account: ctx.depositor.as_ref(),
-        account: ctx.escrow_quote_ata.as_ref(),
-        wallet: ctx.escrow.as_ref(),
-        mint: ctx.quote_mint.as_ref(),
-        token_program: ctx.token_program.as_ref(),
-        system_program: ctx.system_program.as_ref(),
+
+    let spot_target_base_bps: u16 = ctx.args.spot_target_base_bps.into();
+    if spot_target_base_bps as u128 > BPS_DENOMINATOR {
+        return Err(LobbyistError::InvalidSpotTarget.into());
+    }
+
+    let strategy = validate_strategy(
+        ctx.args.strategy,
+        ctx.args.bullish_threshold_bps.into(),
+        ctx.args.bearish_threshold_bps.into(),
+        ctx.args.market_making_band_bps.into(),
+    )?;
+    let price_source = EscrowPriceSource::try_from(ctx.args.price_source)?;
+
+    let preferences_delay_seconds: u32 = ctx.args.preferences_delay_seconds.into();
+    if preferences_delay_seconds < MIN_PREFERENCES_DELAY_SECONDS {
+        return Err(LobbyistError::InvalidPreferencesDelay.into());
+    }
+
+    for (account, mint) in [
+        (ctx.escrow_base_ata.as_ref(), ctx.base_mint.as_ref()),
+        (ctx.escrow_quote_ata.as_ref(), ctx.quote_mint.as_ref()),
+        (
+            ctx.escrow_pass_base_ata.as_ref(),
+            ctx.pass_base_mint.as_ref(),
+        ),
+        (
+            ctx.escrow_pass_quote_ata.as_ref(),
+            ctx.pass_quote_mint.as_ref(),
+        ),
+        (
+            ctx.escrow_fail_base_ata.as_ref(),
+            ctx.fail_base_mint.as_ref(),
+        ),
+        (
+            ctx.escrow_fail_quote_ata.as_ref(),
+            ctx.fail_quote_mint.as_ref(),
+        ),
+    ] {
+        CreateIdempotent {
+            funding_account: ctx.depositor.as_ref(),
+            account,
+            wallet: ctx.escrow.as_ref(),
+            mint,
+            token_program: ctx.token_program.as_ref(),
+            system_program: ctx.system_program.as_ref(),
+        }
+        .invoke()?;
     }
-    .invoke()?;
 
     *ctx.escrow.mut_data()? = Escrow {
         bump: ctx.bumps.escrow,
         proposal: *ctx.proposal.key(),
         depositor: *ctx.depositor.key(),
         dao: *ctx.dao.key(),
-        active: false.into(),
-        bullish: ctx.args.bullish,
+        status: EscrowStatus::Paused as u8,
+        strategy: strategy as u8,
         base_mint: *ctx.base_mint.key(),
         quote_mint: *ctx.quote_mint.key(),
         base_amount: 0,
@@ -91,10 +162,77 @@ pub fn initialize_escrow(ctx: InitializeEscrow) -> ProgramResult {
         pass_quote_amount: 0,
         fail_base_amount: 0,
         fail_quote_amount: 0,
-        bullish_threshold_bps: 0,
-        bearish_threshold_bps: 0,
-        _reserved: [0; 9],
+        last_trade_timestamp: 0,
+        window_start_timestamp: 0,
+        window_traded_amount: 0,
+        max_trade_per_window,
+        last_aggregator_timestamps: [0; 3],
+        last_aggregators: [PodU128::default(); 3],
+        pending_preferences_timestamp: 0,
+        trade_window_seconds,
+        max_oracle_age_seconds: ctx.args.max_oracle_age_seconds.into(),
+        trade_cutoff_seconds: ctx.args.trade_cutoff_seconds.into(),
+        preferences_delay_seconds,
+        bullish_threshold_bps: ctx.args.bullish_threshold_bps.into(),
+        bearish_threshold_bps: ctx.args.bearish_threshold_bps.into(),
+        pending_bullish_threshold_bps: 0,
+        pending_bearish_threshold_bps: 0,
+        cranker_fee_bps,
+        max_slippage_bps,
+        spot_target_base_bps,
+        market_making_band_bps: ctx.args.market_making_band_bps.into(),
+        pending_market_making_band_bps: 0,
+        price_source: price_source as u8,
+        pending_strategy: 0,
+        _reserved: [0; 1],
     };
 
     Ok(())
 }
+
+/// Checks the strategy inputs are in range and consistent with each other
+pub(crate) fn validate_strategy(
+    strategy: u8,
+    bullish_threshold_bps: i16,
+    bearish_threshold_bps: i16,
+    market_making_band_bps: u16,
+) -> ProgramResult<EscrowStrategy> {
+    let strategy = EscrowStrategy::try_from(strategy)?;
+
+    if !(0..=MAX_THRESHOLD_BPS).contains(&bullish_threshold_bps) {
+        return Err(LobbyistError::InvalidBullishThreshold.into());
+    }
+    if !(0..=MAX_THRESHOLD_BPS).contains(&bearish_threshold_bps) {
+        return Err(LobbyistError::InvalidBearishThreshold.into());
+    }
+
+    match strategy {
+        EscrowStrategy::Bullish | EscrowStrategy::Bearish => {
+            let (threshold_bps, unused_threshold_bps) = if strategy == EscrowStrategy::Bullish {
+                (bullish_threshold_bps, bearish_threshold_bps)
+            } else {
+                (bearish_threshold_bps, bullish_threshold_bps)
+            };
+            if threshold_bps == 0 {
+                return Err(LobbyistError::MissingStrategyThreshold.into());
+            }
+            if unused_threshold_bps != 0 {
+                return Err(LobbyistError::UnusedStrategyThreshold.into());
+            }
+            // The band is only used by market making escrows
+            if market_making_band_bps != 0 {
+                return Err(LobbyistError::InvalidMarketMakingBand.into());
+            }
+        }
+        EscrowStrategy::MarketMaking => {
+            if bullish_threshold_bps != 0 || bearish_threshold_bps != 0 {
+                return Err(LobbyistError::UnusedStrategyThreshold.into());
+            }
+            if market_making_band_bps == 0 || market_making_band_bps as u128 >= BPS_DENOMINATOR {
+                return Err(LobbyistError::InvalidMarketMakingBand.into());
+            }
+        }
+    }
+
+    Ok(strategy)
+}
@@ -0,0 +1,169 @@
+use {
+    crate::{
+        conditional_vault_cpi::MergeTokens,
+        errors::LobbyistError,
+        futarchy_cpi::{Market, Proposal},
+        state::Escrow,
+    },
+    typhoon::prelude::*,
+    typhoon_token::{Mint, TokenAccount, TokenProgram},
+};
+
+#[context]
+pub struct Merge {
+    #[constraint(
+        has_one = base_vault @ LobbyistError::InvalidConditionalVault,
+        has_one = quote_vault @ LobbyistError::InvalidConditionalVault,
+        has_one = question @ LobbyistError::InvalidQuestion,
+        has_one = pass_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = pass_quote_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_quote_mint @ LobbyistError::InvalidConditionalMint,
+    )]
+    pub proposal: BorshAccount<Proposal>,
+    #[constraint(
+        seeded,
+        has_one = proposal @ LobbyistError::InvalidProposal,
+        has_one = base_mint @ LobbyistError::InvalidBaseMint,
+        has_one = quote_mint @ LobbyistError::InvalidQuoteMint,
+        bump = escrow.data_unchecked()?.bump,
+    )]
+    pub escrow: Mut<Account<Escrow>>,
+    pub base_mint: Account<Mint>,
+    pub quote_mint: Account<Mint>,
+    pub pass_base_mint: Mut<Account<Mint>>,
+    pub pass_quote_mint: Mut<Account<Mint>>,
+    pub fail_base_mint: Mut<Account<Mint>>,
+    pub fail_quote_mint: Mut<Account<Mint>>,
+    #[constraint(
+        associated_token::mint = base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_quote_ata: Mut<Account<TokenAccount>>,
+    /// Accounts below are validated by the conditional vault program
+    pub base_vault: Mut<UncheckedAccount>,
+    pub base_vault_underlying_token_account: Mut<UncheckedAccount>,
+    pub quote_vault: Mut<UncheckedAccount>,
+    pub quote_vault_underlying_token_account: Mut<UncheckedAccount>,
+    pub question: UncheckedAccount,
+    pub vault_event_authority: UncheckedAccount,
+    pub conditional_vault_program: UncheckedAccount,
+    pub token_program: Program<TokenProgram>,
+}
+
+/// Merges matching pass and fail tokens held by the escrow back into spot tokens
+pub fn merge(ctx: Merge) -> ProgramResult {
+    msg!("Merge");
+
+    let escrow = *ctx.escrow.data()?;
+    let base_amount = escrow.pass_base_amount.min(escrow.fail_base_amount);
+    let quote_amount = escrow.pass_quote_amount.min(escrow.fail_quote_amount);
+
+    let bump = [escrow.bump];
+    let seeds = Escrow::derive_signer_seeds_with_bump(&escrow.depositor, &escrow.proposal, &bump);
+
+    for (
+        amount,
+        vault,
+        vault_underlying_token_account,
+        user_underlying_token_account,
+        conditionals,
+    ) in [
+        (
+            base_amount,
+            ctx.base_vault.as_ref(),
+            ctx.base_vault_underlying_token_account.as_ref(),
+            ctx.escrow_base_ata.as_ref(),
+            [
+                ctx.fail_base_mint.as_ref(),
+                ctx.pass_base_mint.as_ref(),
+                ctx.escrow_fail_base_ata.as_ref(),
+                ctx.escrow_pass_base_ata.as_ref(),
+            ],
+        ),
+        (
+            quote_amount,
+            ctx.quote_vault.as_ref(),
+            ctx.quote_vault_underlying_token_account.as_ref(),
+            ctx.escrow_quote_ata.as_ref(),
+            [
+                ctx.fail_quote_mint.as_ref(),
+                ctx.pass_quote_mint.as_ref(),
+                ctx.escrow_fail_quote_ata.as_ref(),
+                ctx.escrow_pass_quote_ata.as_ref(),
+            ],
+        ),
+    ] {
+        if amount == 0 {
+            continue;
+        }
+
+        MergeTokens {
+            question: ctx.question.as_ref(),
+            vault,
+            vault_underlying_token_account,
+            authority: ctx.escrow.as_ref(),
+            user_underlying_token_account,
+            token_program: ctx.token_program.as_ref(),
+            event_authority: ctx.vault_event_authority.as_ref(),
+            program: ctx.conditional_vault_program.as_ref(),
+            amount,
+        }
+        .invoke_signed_with_remaining(&[instruction::CpiSigner::from(&seeds)], &conditionals)?;
+    }
+    msg!(format!("Merged {} base and {} quote", base_amount, quote_amount).as_str());
+
+    let mut escrow = ctx.escrow.mut_data()?;
+    escrow.base_amount += base_amount;
+    escrow.pass_base_amount -= base_amount;
+    escrow.fail_base_amount -= base_amount;
+    escrow.quote_amount += quote_amount;
+    escrow.pass_quote_amount -= quote_amount;
+    escrow.fail_quote_amount -= quote_amount;
+    for (market, base_ata, quote_ata) in [
+        (Market::Spot, &ctx.escrow_base_ata, &ctx.escrow_quote_ata),
+        (
+            Market::Pass,
+            &ctx.escrow_pass_base_ata,
+            &ctx.escrow_pass_quote_ata,
+        ),
+        (
+            Market::Fail,
+            &ctx.escrow_fail_base_ata,
+            &ctx.escrow_fail_quote_ata,
+        ),
+    ] {
+        escrow.check_backing(
+            &market,
+            base_ata.data()?.amount(),
+            quote_ata.data()?.amount(),
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,6 +1,20 @@
+mod close_escrow;
+mod cross_escrows;
 mod deposit;
 mod initialize_escrow;
+mod merge;
+mod quote_trade;
+mod set_status;
+mod settle_escrow;
+mod sync_escrow;
 mod trade;
+mod trade_batch;
+mod update_preferences;
 mod withdraw;
+mod withdraw_conditional;
 
-pub use {deposit::*, initialize_escrow::*, trade::*, withdraw::*};
+pub use {
+    close_escrow::*, cross_escrows::*, deposit::*, initialize_escrow::*, merge::*, quote_trade::*,
+    set_status::*, settle_escrow::*, sync_escrow::*, trade::*, trade_batch::*,
+    update_preferences::*, withdraw::*, withdraw_conditional::*,
+};
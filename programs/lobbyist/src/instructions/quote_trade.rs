@@ -0,0 +1,57 @@
+use {
+    super::trade::{plan_trade, PlannedSwap, Trade},
+    crate::utils::{PodBool8, PodU64},
+    bytemuck::{AnyBitPattern, NoUninit},
+    typhoon::prelude::*,
+};
+
+/// Swap the next crank of an escrow would make
+#[derive(Debug, Default, PartialEq, AnyBitPattern, NoUninit, Copy, Clone)]
+#[repr(C)]
+pub struct TradeQuote {
+    /// Whether the crank would trade at all
+    pub trade: PodBool8,
+    /// The [`Market`] traded in
+    pub market: u8,
+    /// The [`SwapType`] of the swap
+    pub swap_type: u8,
+    pub input_amount: PodU64,
+    /// Output of the swap at current reserves
+    pub expected_output_amount: PodU64,
+    pub min_output_amount: PodU64,
+    /// Fee paid to the cranker, in the spot token of the swap input
+    pub cranker_fee: PodU64,
+}
+
+/// Runs the trading logic without swapping and returns the planned trade for simulations
+pub fn quote_trade(ctx: Trade, _remaining_accounts: Remaining) -> ProgramResult<TradeQuote> {
+    msg!("Quote trade");
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut escrow = *ctx.escrow.data()?;
+    escrow.apply_pending_preferences(now);
+    let plan = plan_trade(&ctx, &escrow, now)?;
+
+    let Some(PlannedSwap {
+        params,
+        expected_output,
+    }) = plan.swap
+    else {
+        msg!("Nothing to trade");
+        return Ok(TradeQuote::default());
+    };
+
+    let cranker_fee = escrow
+        .cranker_fee(params.input_amount)
+        .min(escrow.fee_balance(&params.swap_type));
+
+    Ok(TradeQuote {
+        trade: true.into(),
+        market: params.market as u8,
+        swap_type: params.swap_type as u8,
+        input_amount: params.input_amount.into(),
+        expected_output_amount: expected_output.into(),
+        min_output_amount: params.min_output_amount.into(),
+        cranker_fee: cranker_fee.into(),
+    })
+}
@@ -0,0 +1,43 @@
+use {
+    crate::{
+        errors::LobbyistError,
+        state::{Escrow, EscrowStatus},
+    },
+    bytemuck::{AnyBitPattern, NoUninit},
+    typhoon::prelude::*,
+};
+
+#[derive(Debug, PartialEq, AnyBitPattern, NoUninit, Copy, Clone)]
+#[repr(C)]
+pub struct SetStatusArgs {
+    /// The [`EscrowStatus`] to move the escrow to
+    pub status: u8,
+}
+
+#[context]
+#[args(SetStatusArgs)]
+pub struct SetStatus {
+    pub depositor: Signer,
+    #[constraint(
+        seeded,
+        bump = escrow.data_unchecked()?.bump,
+        has_one = depositor @ LobbyistError::InvalidDepositor,
+    )]
+    pub escrow: Mut<Account<Escrow>>,
+}
+
+/// Pauses or activates the escrow, only settlement moves it to Done
+pub fn set_status(ctx: SetStatus) -> ProgramResult {
+    msg!("Set status");
+
+    let status = EscrowStatus::try_from(ctx.args.status)?;
+    if status == EscrowStatus::Done {
+        return Err(LobbyistError::StatusRequiresSettlement.into());
+    }
+
+    let mut escrow = ctx.escrow.mut_data()?;
+    EscrowStatus::try_from(escrow.status)?.check_transition(status)?;
+    escrow.status = status as u8;
+
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use {
+    crate::{
+        conditional_vault_cpi::RedeemTokens,
+        errors::LobbyistError,
+        futarchy_cpi::{Proposal, ProposalState},
+        state::{Escrow, EscrowStatus},
+    },
+    typhoon::prelude::*,
+    typhoon_token::{spl_instructions::TransferChecked, Mint, TokenAccount, TokenProgram},
+};
+
+#[context]
+pub struct SettleEscrow {
+    pub depositor: UncheckedAccount,
+    #[constraint(
+        has_one = base_vault @ LobbyistError::InvalidConditionalVault,
+        has_one = quote_vault @ LobbyistError::InvalidConditionalVault,
+        has_one = question @ LobbyistError::InvalidQuestion,
+        has_one = pass_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = pass_quote_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_quote_mint @ LobbyistError::InvalidConditionalMint,
+    )]
+    pub proposal: BorshAccount<Proposal>,
+    #[constraint(
+        seeded,
+        has_one = depositor @ LobbyistError::InvalidDepositor,
+        has_one = proposal @ LobbyistError::InvalidProposal,
+        has_one = base_mint @ LobbyistError::InvalidBaseMint,
+        has_one = quote_mint @ LobbyistError::InvalidQuoteMint,
+        bump = escrow.data_unchecked()?.bump,
+    )]
+    pub escrow: Mut<Account<Escrow>>,
+    pub base_mint: Account<Mint>,
+    pub quote_mint: Account<Mint>,
+    pub pass_base_mint: Mut<Account<Mint>>,
+    pub pass_quote_mint: Mut<Account<Mint>>,
+    pub fail_base_mint: Mut<Account<Mint>>,
+    pub fail_quote_mint: Mut<Account<Mint>>,
+    #[constraint(
+        associated_token::mint = base_mint,
+        associated_token::authority = depositor,
+    )]
+    pub user_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = quote_mint,
+        associated_token::authority = depositor,
+    )]
+    pub user_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_quote_ata: Mut<Account<TokenAccount>>,
+    /// Accounts below are validated by the conditional vault program
+    pub base_vault: Mut<UncheckedAccount>,
+    pub base_vault_underlying_token_account: Mut<UncheckedAccount>,
+    pub quote_vault: Mut<UncheckedAccount>,
+    pub quote_vault_underlying_token_account: Mut<UncheckedAccount>,
+    pub question: UncheckedAccount,
+    pub vault_event_authority: UncheckedAccount,
+    pub conditional_vault_program: UncheckedAccount,
+    pub token_program: Program<TokenProgram>,
+}
+
+/// Redeems the escrow conditional tokens once the proposal is finalized and returns the
+/// underlying tokens to the depositor
+pub fn settle_escrow(ctx: SettleEscrow) -> ProgramResult {
+    msg!("Settle escrow");
+
+    let escrow = *ctx.escrow.data()?;
+    EscrowStatus::try_from(escrow.status)?.check_transition(EscrowStatus::Done)?;
+
+    match ctx.proposal.data()?.state {
+        ProposalState::Passed => msg!("Passed"),
+        ProposalState::Failed => msg!("Failed"),
+        _ => return Err(LobbyistError::ProposalNotFinalized.into()),
+    }
+
+    let bump = [escrow.bump];
+    let seeds = Escrow::derive_signer_seeds_with_bump(&escrow.depositor, &escrow.proposal, &bump);
+
+    let base_redeemed = redeem(
+        &ctx,
+        &[instruction::CpiSigner::from(&seeds)],
+        escrow.pass_base_amount.max(escrow.fail_base_amount),
+        ctx.base_vault.as_ref(),
+        ctx.base_vault_underlying_token_account.as_ref(),
+        &ctx.escrow_base_ata,
+        [
+            ctx.fail_base_mint.as_ref(),
+            ctx.pass_base_mint.as_ref(),
+            ctx.escrow_fail_base_ata.as_ref(),
+            ctx.escrow_pass_base_ata.as_ref(),
+        ],
+    )?;
+    let quote_redeemed = redeem(
+        &ctx,
+        &[instruction::CpiSigner::from(&seeds)],
+        escrow.pass_quote_amount.max(escrow.fail_quote_amount),
+        ctx.quote_vault.as_ref(),
+        ctx.quote_vault_underlying_token_account.as_ref(),
+        &ctx.escrow_quote_ata,
+        [
+            ctx.fail_quote_mint.as_ref(),
+            ctx.pass_quote_mint.as_ref(),
+            ctx.escrow_fail_quote_ata.as_ref(),
+            ctx.escrow_pass_quote_ata.as_ref(),
+        ],
+    )?;
+    msg!(format!(
+        "Redeemed {} base and {} quote",
+        base_redeemed, quote_redeemed
+    )
+    .as_str());
+
+    let base_amount = escrow.base_amount + base_redeemed;
+    let quote_amount = escrow.quote_amount + quote_redeemed;
+
+    for (amount, from, mint, to) in [
+        (
+            base_amount,
+            &ctx.escrow_base_ata,
+            &ctx.base_mint,
+            &ctx.user_base_ata,
+        ),
+        (
+            quote_amount,
+            &ctx.escrow_quote_ata,
+            &ctx.quote_mint,
+            &ctx.user_quote_ata,
+        ),
+    ] {
+        if amount == 0 {
+            continue;
+        }
+
+        TransferChecked {
+            from: from.as_ref(),
+            mint: mint.as_ref(),
+            to: to.as_ref(),
+            authority: ctx.escrow.as_ref(),
+            amount,
+            decimals: mint.data()?.decimals(),
+        }
+        .invoke_signed(&[instruction::CpiSigner::from(&seeds)])?;
+    }
+
+    let mut escrow = ctx.escrow.mut_data()?;
+    escrow.base_amount = 0;
+    escrow.quote_amount = 0;
+    escrow.pass_base_amount = 0;
+    escrow.pass_quote_amount = 0;
+    escrow.fail_base_amount = 0;
+    escrow.fail_quote_amount = 0;
+    escrow.status = EscrowStatus::Done as u8;
+
+    Ok(())
+}
+
+/// Redeems the escrow conditional tokens of a vault and returns the underlying amount received
+fn redeem(
+    ctx: &SettleEscrow,
+    signers: &[instruction::CpiSigner],
+    conditional_amount: u64,
+    vault: &AccountInfo,
+    vault_underlying_token_account: &AccountInfo,
+    escrow_underlying_ata: &Mut<Account<TokenAccount>>,
+    conditionals: [&AccountInfo; 4],
+) -> ProgramResult<u64> {
+    if conditional_amount == 0 {
+        return Ok(0);
+    }
+
+    let underlying_before = escrow_underlying_ata.data()?.amount();
+
+    RedeemTokens {
+        question: ctx.question.as_ref(),
+        vault,
+        vault_underlying_token_account,
+        authority: ctx.escrow.as_ref(),
+        user_underlying_token_account: escrow_underlying_ata.as_ref(),
+        token_program: ctx.token_program.as_ref(),
+        event_authority: ctx.vault_event_authority.as_ref(),
+        program: ctx.conditional_vault_program.as_ref(),
+    }
+    .invoke_signed_with_remaining(signers, &conditionals)?;
+
+    Ok(escrow_underlying_ata.data()?.amount() - underlying_before)
+}
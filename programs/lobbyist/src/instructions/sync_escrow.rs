@@ -0,0 +1,89 @@
+use {
+    crate::{errors::LobbyistError, futarchy_cpi::Proposal, state::Escrow},
+    typhoon::prelude::*,
+    typhoon_token::{Mint, TokenAccount},
+};
+
+#[context]
+pub struct SyncEscrow {
+    pub authority: Signer,
+    #[constraint(
+        has_one = pass_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = pass_quote_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_quote_mint @ LobbyistError::InvalidConditionalMint,
+    )]
+    pub proposal: BorshAccount<Proposal>,
+    #[constraint(
+        seeded,
+        bump = escrow.data_unchecked()?.bump,
+        has_one = proposal @ LobbyistError::InvalidProposal,
+        has_one = base_mint @ LobbyistError::InvalidBaseMint,
+        has_one = quote_mint @ LobbyistError::InvalidQuoteMint,
+    )]
+    pub escrow: Mut<Account<Escrow>>,
+    pub base_mint: Account<Mint>,
+    pub quote_mint: Account<Mint>,
+    pub pass_base_mint: Account<Mint>,
+    pub pass_quote_mint: Account<Mint>,
+    pub fail_base_mint: Account<Mint>,
+    pub fail_quote_mint: Account<Mint>,
+    #[constraint(
+        associated_token::mint = base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_base_ata: Account<TokenAccount>,
+    #[constraint(
+        associated_token::mint = quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_quote_ata: Account<TokenAccount>,
+    #[constraint(
+        associated_token::mint = pass_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_base_ata: Account<TokenAccount>,
+    #[constraint(
+        associated_token::mint = pass_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_quote_ata: Account<TokenAccount>,
+    #[constraint(
+        associated_token::mint = fail_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_base_ata: Account<TokenAccount>,
+    #[constraint(
+        associated_token::mint = fail_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_quote_ata: Account<TokenAccount>,
+}
+
+/// Resets the tracked amounts of the escrow to the balances of its token accounts.
+///
+/// Anyone can lower tracked amounts the balances no longer back, only the depositor can raise
+/// them to tokens sent to the escrow so that donations cannot keep a paused escrow from closing.
+pub fn sync_escrow(ctx: SyncEscrow) -> ProgramResult {
+    msg!("Sync escrow");
+
+    let mut data = ctx.escrow.mut_data()?;
+    let escrow = &mut *data;
+    let is_depositor = *ctx.authority.key() == escrow.depositor;
+    for (amount, token_account) in [
+        (&mut escrow.base_amount, &ctx.escrow_base_ata),
+        (&mut escrow.quote_amount, &ctx.escrow_quote_ata),
+        (&mut escrow.pass_base_amount, &ctx.escrow_pass_base_ata),
+        (&mut escrow.pass_quote_amount, &ctx.escrow_pass_quote_ata),
+        (&mut escrow.fail_base_amount, &ctx.escrow_fail_base_ata),
+        (&mut escrow.fail_quote_amount, &ctx.escrow_fail_quote_ata),
+    ] {
+        let balance = token_account.data()?.amount();
+        if balance < *amount || (balance > *amount && is_depositor) {
+            msg!(format!("Synced {} to {}", amount, balance).as_str());
+            *amount = balance;
+        }
+    }
+
+    Ok(())
+}
@@ -1,24 +1,49 @@
 use {
     crate::{
+        conditional_vault_cpi::SplitTokens,
         errors::LobbyistError,
-        futarchy_cpi::{Dao, Pool, PoolState, Proposal},
-        state::Escrow,
+        futarchy_cpi::{
+            ConditionalSwap, ConditionalSwapParams, Dao, Market, Pool, PoolState, Proposal,
+            ProposalState, SpotSwap, SpotSwapParams, SwapType,
+        },
+        state::{Escrow, EscrowPriceSource, EscrowStatus, EscrowStrategy},
+        utils::{
+            buy_input_to_target, check_trading_open, min_output, output_at_price, reserves_price,
+            sell_input_to_target, swap_output, SliceReader, AMM_FEE_BPS, BPS_DENOMINATOR,
+            PRICE_SCALE,
+        },
     },
+    borsh::BorshDeserialize,
     typhoon::prelude::*,
-    typhoon_token::{AtaTokenProgram, Mint, TokenProgram},
+    typhoon_token::{
+        spl_instructions::TransferChecked, AtaTokenProgram, Mint, TokenAccount, TokenProgram,
+    },
 };
 
-pub const MAXIMUM_AGE: u64 = 10; // 10 seconds
+/// Default maximum age of a traded pool oracle.
+///
+/// Pool oracles are only updated by AMM swaps, so with this default a crank has to follow a
+/// swap in the traded markets within seconds, typically by bundling one ahead of it. Escrows
+/// cranked on their own set a longer `max_oracle_age_seconds`.
+pub const MAXIMUM_AGE: u32 = 10; // 10 seconds
 
 #[context]
 pub struct Trade {
-    pub depositor: Mut<Signer>,
-    pub dao: BorshAccount<Dao>,
+    pub cranker: Mut<Signer>,
+    pub dao: Mut<BorshAccount<Dao>>,
+    #[constraint(
+        has_one = base_vault @ LobbyistError::InvalidConditionalVault,
+        has_one = quote_vault @ LobbyistError::InvalidConditionalVault,
+        has_one = question @ LobbyistError::InvalidQuestion,
+        has_one = pass_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = pass_quote_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_quote_mint @ LobbyistError::InvalidConditionalMint,
+    )]
     pub proposal: BorshAccount<Proposal>,
     #[constraint(
         seeded,
         has_one = dao @ LobbyistError::InvalidDao,
-        has_one = depositor @ LobbyistError::InvalidDepositor,
         has_one = proposal @ LobbyistError::InvalidProposal,
         has_one = base_mint @ LobbyistError::InvalidBaseMint,
         has_one = quote_mint @ LobbyistError::InvalidQuoteMint,
@@ -27,37 +52,883 @@ pub struct Trade {
     pub escrow: Mut<Account<Escrow>>,
     pub base_mint: Account<Mint>,
     pub quote_mint: Account<Mint>,
-    pub escrow_base_ata: Mut<UncheckedAccount>,
-    pub escrow_quote_ata: Mut<UncheckedAccount>,
+    pub pass_base_mint: Mut<Account<Mint>>,
+    pub pass_quote_mint: Mut<Account<Mint>>,
+    pub fail_base_mint: Mut<Account<Mint>>,
+    pub fail_quote_mint: Mut<Account<Mint>>,
+    #[constraint(
+        associated_token::mint = base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = quote_mint,
+        associated_token::authority = cranker,
+    )]
+    pub cranker_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = base_mint,
+        associated_token::authority = cranker,
+    )]
+    pub cranker_base_ata: Mut<Account<TokenAccount>>,
+    /// Accounts below are validated by the futarchy and conditional vault programs
+    pub amm_base_vault: Mut<UncheckedAccount>,
+    pub amm_quote_vault: Mut<UncheckedAccount>,
+    pub amm_pass_base_vault: Mut<UncheckedAccount>,
+    pub amm_pass_quote_vault: Mut<UncheckedAccount>,
+    pub amm_fail_base_vault: Mut<UncheckedAccount>,
+    pub amm_fail_quote_vault: Mut<UncheckedAccount>,
+    pub base_vault: Mut<UncheckedAccount>,
+    pub base_vault_underlying_token_account: Mut<UncheckedAccount>,
+    pub quote_vault: Mut<UncheckedAccount>,
+    pub quote_vault_underlying_token_account: Mut<UncheckedAccount>,
+    pub question: UncheckedAccount,
+    pub vault_event_authority: UncheckedAccount,
+    pub conditional_vault_program: UncheckedAccount,
+    pub futarchy_event_authority: UncheckedAccount,
+    pub futarchy_program: UncheckedAccount,
     pub token_program: Program<TokenProgram>,
     pub ata_token_program: Program<AtaTokenProgram>,
     pub system_program: Program<System>,
 }
 
-/// Creates a new escrow for a given lobbyist
+/// Accounts of a single escrow traded by a crank
+pub(crate) struct EscrowAccounts<'a, 'info> {
+    pub escrow: &'a Mut<Account<'info, Escrow>>,
+    pub escrow_base_ata: &'a Mut<Account<'info, TokenAccount>>,
+    pub escrow_quote_ata: &'a Mut<Account<'info, TokenAccount>>,
+    pub escrow_pass_base_ata: &'a Mut<Account<'info, TokenAccount>>,
+    pub escrow_pass_quote_ata: &'a Mut<Account<'info, TokenAccount>>,
+    pub escrow_fail_base_ata: &'a Mut<Account<'info, TokenAccount>>,
+    pub escrow_fail_quote_ata: &'a Mut<Account<'info, TokenAccount>>,
+}
+
+impl<'a, 'info> EscrowAccounts<'a, 'info> {
+    /// Returns the accounts of the escrow traded by the context
+    pub(crate) fn from_context(ctx: &'a Trade<'info>) -> Self {
+        Self {
+            escrow: &ctx.escrow,
+            escrow_base_ata: &ctx.escrow_base_ata,
+            escrow_quote_ata: &ctx.escrow_quote_ata,
+            escrow_pass_base_ata: &ctx.escrow_pass_base_ata,
+            escrow_pass_quote_ata: &ctx.escrow_pass_quote_ata,
+            escrow_fail_base_ata: &ctx.escrow_fail_base_ata,
+            escrow_fail_quote_ata: &ctx.escrow_fail_quote_ata,
+        }
+    }
+
+    /// Returns the escrow token accounts holding the (base, quote) tokens of a market
+    fn market_atas(
+        &self,
+        market: &Market,
+    ) -> (
+        &Mut<Account<'info, TokenAccount>>,
+        &Mut<Account<'info, TokenAccount>>,
+    ) {
+        match market {
+            Market::Spot => (self.escrow_base_ata, self.escrow_quote_ata),
+            Market::Pass => (self.escrow_pass_base_ata, self.escrow_pass_quote_ata),
+            Market::Fail => (self.escrow_fail_base_ata, self.escrow_fail_quote_ata),
+        }
+    }
+
+    /// Checks the tracked balances of every market are held by the escrow token accounts
+    pub(crate) fn check_backing(&self) -> ProgramResult {
+        let escrow = self.escrow.data()?;
+        for market in [Market::Spot, Market::Pass, Market::Fail] {
+            let (base_ata, quote_ata) = self.market_atas(&market);
+            escrow.check_backing(
+                &market,
+                base_ata.data()?.amount(),
+                quote_ata.data()?.amount(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Swap decided by the trading logic
+pub(crate) struct PlannedSwap {
+    pub params: ConditionalSwapParams,
+    /// Output of the swap at current reserves
+    pub expected_output: u64,
+}
+
+/// Outcome of the trading logic for the current market state
+pub(crate) struct TradePlan {
+    /// Swap to make, none if the escrow is on target
+    pub swap: Option<PlannedSwap>,
+    /// Market, aggregator and update timestamp of the oracles the decision was based on
+    pub snapshots: Vec<(Market, u128, i64)>,
+}
+
+impl TradePlan {
+    fn idle(snapshots: Vec<(Market, u128, i64)>) -> Self {
+        Self {
+            swap: None,
+            snapshots,
+        }
+    }
+}
+
+/// Trades the escrow funds in the decision markets according to its preferences
 pub fn trade(ctx: Trade, _remaining_accounts: Remaining) -> ProgramResult {
     msg!("Trade");
 
-    match &ctx.dao.data()?.amm.state {
+    trade_escrow(
+        &ctx,
+        &EscrowAccounts::from_context(&ctx),
+        Clock::get()?.unix_timestamp,
+    )
+}
+
+/// Runs a crank on a single escrow
+fn trade_escrow(ctx: &Trade, accounts: &EscrowAccounts, now: i64) -> ProgramResult {
+    apply_preferences(accounts, now)?;
+    let plan = plan_trade(ctx, &accounts.escrow.data()?, now)?;
+    store_snapshots(accounts, &plan.snapshots)?;
+
+    match plan.swap {
+        Some(PlannedSwap { params, .. }) => execute_swap(ctx, accounts, params, now)?,
+        None => msg!("Nothing to trade"),
+    }
+
+    accounts.check_backing()
+}
+
+/// Switches the escrow to its pending preferences once they took effect
+pub(crate) fn apply_preferences(accounts: &EscrowAccounts, now: i64) -> ProgramResult {
+    if accounts.escrow.mut_data()?.apply_pending_preferences(now) {
+        msg!("Preferences updated");
+    }
+
+    Ok(())
+}
+
+/// Stores the oracle snapshots a trading decision was based on
+pub(crate) fn store_snapshots(
+    accounts: &EscrowAccounts,
+    snapshots: &[(Market, u128, i64)],
+) -> ProgramResult {
+    let mut escrow = accounts.escrow.mut_data()?;
+    for (market, aggregator, timestamp) in snapshots {
+        escrow.set_oracle_snapshot(market, *aggregator, *timestamp);
+    }
+
+    Ok(())
+}
+
+/// Pays the cranker, splits the input the escrow is missing and swaps it in the AMM
+pub(crate) fn execute_swap(
+    ctx: &Trade,
+    accounts: &EscrowAccounts,
+    params: ConditionalSwapParams,
+    now: i64,
+) -> ProgramResult {
+    let escrow = *accounts.escrow.data()?;
+    pay_cranker_fee(
+        ctx,
+        accounts,
+        &escrow,
+        &params.swap_type,
+        params.input_amount,
+    )?;
+
+    let escrow = *accounts.escrow.data()?;
+    split_input(ctx, accounts, &escrow, &params)?;
+
+    let escrow = *accounts.escrow.data()?;
+    swap(ctx, accounts, &escrow, params, now)
+}
+
+/// Runs the trading logic of the escrow against the current market state without side effects
+pub(crate) fn plan_trade(ctx: &Trade, escrow: &Escrow, now: i64) -> ProgramResult<TradePlan> {
+    if EscrowStatus::try_from(escrow.status)? != EscrowStatus::Active {
+        return Err(LobbyistError::EscrowNotActive.into());
+    }
+
+    let budget = escrow.window_budget(now);
+    if budget == 0 {
+        return Err(LobbyistError::TradeWindowExhausted.into());
+    }
+
+    let mut prices = PriceReader::new(escrow, now)?;
+    let swap = match &load_dao(ctx)?.amm.state {
         PoolState::Spot { spot } => {
             msg!("Spot");
-            let spot_twap = get_twap(&spot)?;
-            msg!(format!("{:?}", spot_twap).as_str());
+            // Escrows of a finalized proposal are settled rather than traded, their conditional
+            // tokens having to be redeemed first
+            if matches!(
+                ctx.proposal.data()?.state,
+                ProposalState::Passed | ProposalState::Failed
+            ) {
+                return Err(LobbyistError::ProposalFinalized.into());
+            }
+            let spot_price = prices.market_price(Market::Spot, spot)?;
+            msg!(format!("Spot price: {:?}", spot_price).as_str());
+
+            match spot_rebalance(escrow, spot_price)? {
+                Some((swap_type, input_amount)) => Some(quote_swap(
+                    escrow,
+                    Market::Spot,
+                    spot,
+                    swap_type,
+                    input_amount.min(input_budget(spot, &swap_type, budget)),
+                    spot_price,
+                )?),
+                None => {
+                    msg!("Target reached");
+                    None
+                }
+            }
         }
         PoolState::Futarchy { spot, pass, fail } => {
             msg!("Futarchy");
-            let pass_twap = get_twap(&pass)?;
-            msg!(format!("Pass TWAP: {:?}", pass_twap).as_str());
-            let fail_twap = get_twap(&fail)?;
-            msg!(format!("Fail TWAP: {:?}", fail_twap).as_str());
-            let spot_twap = get_twap(&spot)?;
-            msg!(format!("Spot TWAP: {:?}", spot_twap).as_str());
+            check_trading_open(&ctx.proposal.data()?, now, escrow.trade_cutoff_seconds)?;
+            let spot_price = prices.frozen_spot_price(spot)?;
+            msg!(format!("Spot price: {:?}", spot_price).as_str());
+
+            match EscrowStrategy::try_from(escrow.strategy)? {
+                EscrowStrategy::Bullish => {
+                    bullish_swap(escrow, &mut prices, pass, spot_price, budget)?
+                }
+                EscrowStrategy::Bearish => {
+                    bearish_swap(escrow, &mut prices, pass, fail, spot_price, budget)?
+                }
+                EscrowStrategy::MarketMaking => market_making_swap(
+                    escrow,
+                    &mut prices,
+                    [(Market::Pass, pass), (Market::Fail, fail)],
+                    spot_price,
+                    budget,
+                )?,
+            }
         }
+    };
+
+    Ok(TradePlan {
+        swap: swap.filter(|swap| swap.params.input_amount > 0),
+        snapshots: prices.snapshots,
+    })
+}
+
+/// Reads the Dao from its account data, the copy deserialized with the context going stale once
+/// a swap of the same instruction moved the pools
+pub(crate) fn load_dao(ctx: &Trade) -> ProgramResult<Dao> {
+    let data = ctx.dao.as_ref().try_borrow_data()?;
+    let dao = Dao::deserialize_reader(&mut SliceReader::new(&data[8..]))
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+
+    Ok(dao)
+}
+
+/// Reads the prices a trading decision is based on from the price source of the escrow
+struct PriceReader<'a> {
+    escrow: &'a Escrow,
+    price_source: EscrowPriceSource,
+    now: i64,
+    max_oracle_age: u32,
+    /// Oracle states of the markets read so far
+    snapshots: Vec<(Market, u128, i64)>,
+}
+
+impl<'a> PriceReader<'a> {
+    fn new(escrow: &'a Escrow, now: i64) -> ProgramResult<Self> {
+        Ok(Self {
+            escrow,
+            price_source: EscrowPriceSource::try_from(escrow.price_source)?,
+            now,
+            max_oracle_age: match escrow.max_oracle_age_seconds {
+                0 => MAXIMUM_AGE,
+                age => age,
+            },
+            snapshots: Vec::new(),
+        })
+    }
+
+    /// Price of a traded market, whose oracle has to be fresh
+    fn market_price(&mut self, market: Market, pool: &Pool) -> ProgramResult<u128> {
+        check_oracle_age(pool, self.now, self.max_oracle_age)?;
+        let price = get_price(pool, &market, self.escrow, self.price_source)?;
+        self.snapshots.push(pool_snapshot(market, pool));
+
+        Ok(price)
+    }
+
+    /// Price of the spot market while a proposal is live.
+    ///
+    /// The spot pool does not trade until the proposal is finalized, so its oracle is never
+    /// fresh. TWAP sources use its lifetime TWAP, a window since the last crank holding no new
+    /// observation, and instantaneous sources use its last observation.
+    fn frozen_spot_price(&self, spot: &Pool) -> ProgramResult<u128> {
+        match self.price_source {
+            EscrowPriceSource::WindowedTwap | EscrowPriceSource::LifetimeTwap => get_twap(spot),
+            EscrowPriceSource::LastObservation | EscrowPriceSource::Reserves => {
+                Ok(spot.oracle.last_observation)
+            }
+        }
+    }
+}
+
+/// Swap buying the pass market up to the bullish threshold of the spot price
+fn bullish_swap(
+    escrow: &Escrow,
+    prices: &mut PriceReader,
+    pass: &Pool,
+    spot_price: u128,
+    budget: u64,
+) -> ProgramResult<Option<PlannedSwap>> {
+    let pass_price = prices.market_price(Market::Pass, pass)?;
+    msg!(format!("Pass price: {:?}", pass_price).as_str());
+
+    let target_price = spot_price * escrow.bullish_threshold_bps.max(0) as u128 / BPS_DENOMINATOR;
+    if pass_price >= target_price {
+        msg!(format!("Target reached: {} >= {}", pass_price, target_price).as_str());
+        return Ok(None);
     }
 
+    size_swap(
+        escrow,
+        Market::Pass,
+        pass,
+        SwapType::Buy,
+        target_price,
+        budget,
+    )
+    .map(Some)
+}
+
+/// Swap buying the fail market up to the bearish threshold of the spot price, or selling the
+/// pass market down to the mirrored target once it is reached or nothing is left to buy with
+fn bearish_swap(
+    escrow: &Escrow,
+    prices: &mut PriceReader,
+    pass: &Pool,
+    fail: &Pool,
+    spot_price: u128,
+    budget: u64,
+) -> ProgramResult<Option<PlannedSwap>> {
+    let threshold_bps = escrow.bearish_threshold_bps.max(0) as u128;
+    let fail_price = prices.market_price(Market::Fail, fail)?;
+    msg!(format!("Fail price: {:?}", fail_price).as_str());
+
+    let target_price = spot_price * threshold_bps / BPS_DENOMINATOR;
+    if fail_price < target_price {
+        let swap = size_swap(
+            escrow,
+            Market::Fail,
+            fail,
+            SwapType::Buy,
+            target_price,
+            budget,
+        )?;
+        if swap.params.input_amount > 0 {
+            return Ok(Some(swap));
+        }
+        // Nothing to buy the fail market with, the pass market can still be pushed down
+        if escrow.input_available(&Market::Fail, &SwapType::Buy) == 0 {
+            msg!("No quote tokens to buy the fail market with");
+        } else {
+            msg!(format!("Fail reserves already at target {}", target_price).as_str());
+        }
+    } else {
+        msg!(format!("Fail target reached: {} >= {}", fail_price, target_price).as_str());
+    }
+    if threshold_bps == 0 {
+        return Ok(None);
+    }
+
+    let pass_price = prices.market_price(Market::Pass, pass)?;
+    msg!(format!("Pass price: {:?}", pass_price).as_str());
+
+    let target_price = spot_price * BPS_DENOMINATOR / threshold_bps;
+    if pass_price <= target_price {
+        msg!(format!("Target reached: {} <= {}", pass_price, target_price).as_str());
+        return Ok(None);
+    }
+
+    size_swap(
+        escrow,
+        Market::Pass,
+        pass,
+        SwapType::Sell,
+        target_price,
+        budget,
+    )
+    .map(Some)
+}
+
+/// Swap bringing the first market found outside the band around spot back to its edge
+fn market_making_swap(
+    escrow: &Escrow,
+    prices: &mut PriceReader,
+    markets: [(Market, &Pool); 2],
+    spot_price: u128,
+    budget: u64,
+) -> ProgramResult<Option<PlannedSwap>> {
+    let band_bps = escrow.market_making_band_bps as u128;
+    let lower_price = spot_price * (BPS_DENOMINATOR - band_bps) / BPS_DENOMINATOR;
+    let upper_price = spot_price * (BPS_DENOMINATOR + band_bps) / BPS_DENOMINATOR;
+
+    for (market, pool) in markets {
+        let price = prices.market_price(market.clone(), pool)?;
+        let swap = if price < lower_price {
+            size_swap(escrow, market, pool, SwapType::Buy, lower_price, budget)?
+        } else if price > upper_price {
+            size_swap(escrow, market, pool, SwapType::Sell, upper_price, budget)?
+        } else {
+            continue;
+        };
+
+        if swap.params.input_amount > 0 {
+            return Ok(Some(swap));
+        }
+    }
+
+    msg!("Markets within band");
+    Ok(None)
+}
+
+/// Sizes a swap moving the pool price to the target with the funds the escrow can trade
+fn size_swap(
+    escrow: &Escrow,
+    market: Market,
+    pool: &Pool,
+    swap_type: SwapType,
+    target_price: u128,
+    budget: u64,
+) -> ProgramResult<PlannedSwap> {
+    let balance = escrow
+        .input_available(&market, &swap_type)
+        .min(input_budget(pool, &swap_type, budget));
+    let input_amount = match swap_type {
+        SwapType::Buy => buy_input_to_target(
+            pool.base_reserves,
+            pool.quote_reserves,
+            target_price,
+            AMM_FEE_BPS,
+            balance,
+        ),
+        SwapType::Sell => sell_input_to_target(
+            pool.base_reserves,
+            pool.quote_reserves,
+            target_price,
+            AMM_FEE_BPS,
+            balance,
+        ),
+    }
+    .ok_or(LobbyistError::MathOverflow)?;
+
+    quote_swap(escrow, market, pool, swap_type, input_amount, target_price)
+}
+
+/// Input amount whose quote value fits the window budget of the escrow
+fn input_budget(pool: &Pool, swap_type: &SwapType, budget: u64) -> u64 {
+    match swap_type {
+        SwapType::Buy => budget,
+        // Valued at the pool price before the swap, the highest price a sell goes through at
+        SwapType::Sell => (budget as u128 * pool.base_reserves as u128)
+            .checked_div(pool.quote_reserves as u128)
+            .map_or(budget, |amount| amount.min(u64::MAX as u128) as u64),
+    }
+}
+
+/// Expected and minimum outputs of swapping `input_amount` into the pool.
+///
+/// The minimum output applies the escrow slippage to the output at the reference price the
+/// trading decision was based on, rather than to the current reserves which a crank can move
+/// before trading. Swaps whose output at current reserves already falls short are rejected
+/// before any CPI.
+pub(crate) fn quote_swap(
+    escrow: &Escrow,
+    market: Market,
+    pool: &Pool,
+    swap_type: SwapType,
+    input_amount: u64,
+    reference_price: u128,
+) -> ProgramResult<PlannedSwap> {
+    let (input_reserves, output_reserves) = match swap_type {
+        SwapType::Buy => (pool.quote_reserves, pool.base_reserves),
+        SwapType::Sell => (pool.base_reserves, pool.quote_reserves),
+    };
+    let expected_output = swap_output(input_reserves, output_reserves, input_amount, AMM_FEE_BPS)
+        .ok_or(LobbyistError::MathOverflow)?;
+    let reference_output = output_at_price(
+        input_amount,
+        reference_price,
+        AMM_FEE_BPS,
+        matches!(swap_type, SwapType::Buy),
+    )
+    .ok_or(LobbyistError::MathOverflow)?;
+    let min_output_amount =
+        min_output(reference_output, escrow.max_slippage_bps).ok_or(LobbyistError::MathOverflow)?;
+
+    if expected_output < min_output_amount {
+        msg!(format!(
+            "Expected output {} below minimum {}",
+            expected_output, min_output_amount
+        )
+        .as_str());
+        return Err(LobbyistError::SlippageExceeded.into());
+    }
+
+    Ok(PlannedSwap {
+        params: ConditionalSwapParams {
+            input_amount,
+            market,
+            swap_type,
+            min_output_amount,
+        },
+        expected_output,
+    })
+}
+
+/// Splits the spot tokens missing for the escrow to hold the swap input in conditional tokens
+pub(crate) fn split_input(
+    ctx: &Trade,
+    accounts: &EscrowAccounts,
+    escrow: &Escrow,
+    params: &ConditionalSwapParams,
+) -> ProgramResult {
+    let (base_amount, quote_amount) = escrow.market_amounts(&params.market);
+    let held = match params.swap_type {
+        SwapType::Buy => quote_amount,
+        SwapType::Sell => base_amount,
+    };
+    let amount = params.input_amount.saturating_sub(held);
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let (vault, vault_underlying_token_account, user_underlying_token_account, conditionals) =
+        match params.swap_type {
+            SwapType::Buy => (
+                ctx.quote_vault.as_ref(),
+                ctx.quote_vault_underlying_token_account.as_ref(),
+                accounts.escrow_quote_ata.as_ref(),
+                [
+                    ctx.fail_quote_mint.as_ref(),
+                    ctx.pass_quote_mint.as_ref(),
+                    accounts.escrow_fail_quote_ata.as_ref(),
+                    accounts.escrow_pass_quote_ata.as_ref(),
+                ],
+            ),
+            SwapType::Sell => (
+                ctx.base_vault.as_ref(),
+                ctx.base_vault_underlying_token_account.as_ref(),
+                accounts.escrow_base_ata.as_ref(),
+                [
+                    ctx.fail_base_mint.as_ref(),
+                    ctx.pass_base_mint.as_ref(),
+                    accounts.escrow_fail_base_ata.as_ref(),
+                    accounts.escrow_pass_base_ata.as_ref(),
+                ],
+            ),
+        };
+
+    let bump = [escrow.bump];
+    let seeds = Escrow::derive_signer_seeds_with_bump(&escrow.depositor, &escrow.proposal, &bump);
+
+    SplitTokens {
+        question: ctx.question.as_ref(),
+        vault,
+        vault_underlying_token_account,
+        authority: accounts.escrow.as_ref(),
+        user_underlying_token_account,
+        token_program: ctx.token_program.as_ref(),
+        event_authority: ctx.vault_event_authority.as_ref(),
+        program: ctx.conditional_vault_program.as_ref(),
+        amount,
+    }
+    .invoke_signed_with_remaining(&[instruction::CpiSigner::from(&seeds)], &conditionals)?;
+    msg!(format!("Split {}", amount).as_str());
+
+    let mut escrow = accounts.escrow.mut_data()?;
+    match params.swap_type {
+        SwapType::Buy => {
+            escrow.quote_amount -= amount;
+            escrow.pass_quote_amount += amount;
+            escrow.fail_quote_amount += amount;
+        }
+        SwapType::Sell => {
+            escrow.base_amount -= amount;
+            escrow.pass_base_amount += amount;
+            escrow.fail_base_amount += amount;
+        }
+    }
+
+    Ok(())
+}
+
+/// Spot swap bringing the escrow base holdings to their target share of its value, if any
+fn spot_rebalance(escrow: &Escrow, price: u128) -> ProgramResult<Option<(SwapType, u64)>> {
+    if escrow.spot_target_base_bps == 0 {
+        return Ok(None);
+    }
+
+    let base_value = (escrow.base_amount as u128)
+        .checked_mul(price)
+        .ok_or(LobbyistError::MathOverflow)?
+        / PRICE_SCALE;
+    let total_value = base_value + escrow.quote_amount as u128;
+    let target_base_value = total_value * escrow.spot_target_base_bps as u128 / BPS_DENOMINATOR;
+
+    if base_value < target_base_value {
+        let quote_input = (target_base_value - base_value)
+            .min(escrow.input_available(&Market::Spot, &SwapType::Buy) as u128);
+        Ok(Some((SwapType::Buy, quote_input as u64)))
+    } else if base_value > target_base_value && price > 0 {
+        let base_input = ((base_value - target_base_value) * PRICE_SCALE / price)
+            .min(escrow.input_available(&Market::Spot, &SwapType::Sell) as u128);
+        Ok(Some((SwapType::Sell, base_input as u64)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Swaps escrow tokens in the spot or a conditional market and books the actual token
+/// movements
+fn swap(
+    ctx: &Trade,
+    accounts: &EscrowAccounts,
+    escrow: &Escrow,
+    params: ConditionalSwapParams,
+    timestamp: i64,
+) -> ProgramResult {
+    let (base_ata, quote_ata) = accounts.market_atas(&params.market);
+    let (input_ata, output_ata) = match params.swap_type {
+        SwapType::Buy => (quote_ata, base_ata),
+        SwapType::Sell => (base_ata, quote_ata),
+    };
+    let input_before = input_ata.data()?.amount();
+    let output_before = output_ata.data()?.amount();
+
+    let bump = [escrow.bump];
+    let seeds = Escrow::derive_signer_seeds_with_bump(&escrow.depositor, &escrow.proposal, &bump);
+
+    let signers = [instruction::CpiSigner::from(&seeds)];
+    match params.market {
+        Market::Spot => SpotSwap {
+            dao: ctx.dao.as_ref(),
+            user_base_account: accounts.escrow_base_ata.as_ref(),
+            user_quote_account: accounts.escrow_quote_ata.as_ref(),
+            amm_base_vault: ctx.amm_base_vault.as_ref(),
+            amm_quote_vault: ctx.amm_quote_vault.as_ref(),
+            user: accounts.escrow.as_ref(),
+            token_program: ctx.token_program.as_ref(),
+            event_authority: ctx.futarchy_event_authority.as_ref(),
+            program: ctx.futarchy_program.as_ref(),
+            params: SpotSwapParams {
+                input_amount: params.input_amount,
+                swap_type: params.swap_type.clone(),
+                min_output_amount: params.min_output_amount,
+            },
+        }
+        .invoke_signed(&signers)?,
+        Market::Pass | Market::Fail => ConditionalSwap {
+            dao: ctx.dao.as_ref(),
+            amm_base_vault: ctx.amm_base_vault.as_ref(),
+            amm_quote_vault: ctx.amm_quote_vault.as_ref(),
+            proposal: ctx.proposal.as_ref(),
+            amm_pass_base_vault: ctx.amm_pass_base_vault.as_ref(),
+            amm_pass_quote_vault: ctx.amm_pass_quote_vault.as_ref(),
+            amm_fail_base_vault: ctx.amm_fail_base_vault.as_ref(),
+            amm_fail_quote_vault: ctx.amm_fail_quote_vault.as_ref(),
+            trader: accounts.escrow.as_ref(),
+            user_input_account: input_ata.as_ref(),
+            user_output_account: output_ata.as_ref(),
+            base_vault: ctx.base_vault.as_ref(),
+            base_vault_underlying_token_account: ctx.base_vault_underlying_token_account.as_ref(),
+            quote_vault: ctx.quote_vault.as_ref(),
+            quote_vault_underlying_token_account: ctx.quote_vault_underlying_token_account.as_ref(),
+            pass_base_mint: ctx.pass_base_mint.as_ref(),
+            fail_base_mint: ctx.fail_base_mint.as_ref(),
+            pass_quote_mint: ctx.pass_quote_mint.as_ref(),
+            fail_quote_mint: ctx.fail_quote_mint.as_ref(),
+            conditional_vault_program: ctx.conditional_vault_program.as_ref(),
+            vault_event_authority: ctx.vault_event_authority.as_ref(),
+            question: ctx.question.as_ref(),
+            token_program: ctx.token_program.as_ref(),
+            event_authority: ctx.futarchy_event_authority.as_ref(),
+            program: ctx.futarchy_program.as_ref(),
+            params: params.clone(),
+        }
+        .invoke_signed(&signers)?,
+    }
+
+    let spent = input_before - input_ata.data()?.amount();
+    let received = output_ata.data()?.amount() - output_before;
+    msg!(format!("Swapped {} for {}", spent, received).as_str());
+    if received < params.min_output_amount {
+        return Err(LobbyistError::SlippageExceeded.into());
+    }
+
+    let mut escrow = accounts.escrow.mut_data()?;
+    let (base_amount, quote_amount) = escrow.market_amounts_mut(&params.market);
+    let quote_volume = match params.swap_type {
+        SwapType::Buy => {
+            *quote_amount -= spent;
+            *base_amount += received;
+            spent
+        }
+        SwapType::Sell => {
+            *base_amount -= spent;
+            *quote_amount += received;
+            received
+        }
+    };
+    escrow.record_trade(timestamp, quote_volume);
+
     Ok(())
 }
 
+/// Pays the cranker its share of a swap input out of the escrow spot tokens of the input
+pub(crate) fn pay_cranker_fee(
+    ctx: &Trade,
+    accounts: &EscrowAccounts,
+    escrow: &Escrow,
+    swap_type: &SwapType,
+    input_amount: u64,
+) -> ProgramResult {
+    // Set aside when the swap was sized, the spot balance only falls short of it when the input
+    // is already held in conditional tokens
+    let fee = escrow
+        .cranker_fee(input_amount)
+        .min(escrow.fee_balance(swap_type));
+    if fee == 0 {
+        return Ok(());
+    }
+
+    let (from, mint, to) = match swap_type {
+        SwapType::Buy => (
+            accounts.escrow_quote_ata,
+            &ctx.quote_mint,
+            &ctx.cranker_quote_ata,
+        ),
+        SwapType::Sell => (
+            accounts.escrow_base_ata,
+            &ctx.base_mint,
+            &ctx.cranker_base_ata,
+        ),
+    };
+
+    let bump = [escrow.bump];
+    let seeds = Escrow::derive_signer_seeds_with_bump(&escrow.depositor, &escrow.proposal, &bump);
+
+    TransferChecked {
+        from: from.as_ref(),
+        mint: mint.as_ref(),
+        to: to.as_ref(),
+        authority: accounts.escrow.as_ref(),
+        amount: fee,
+        decimals: mint.data()?.decimals(),
+    }
+    .invoke_signed(&[instruction::CpiSigner::from(&seeds)])?;
+    msg!(format!("Cranker fee: {}", fee).as_str());
+
+    let mut escrow = accounts.escrow.mut_data()?;
+    match swap_type {
+        SwapType::Buy => escrow.quote_amount -= fee,
+        SwapType::Sell => escrow.base_amount -= fee,
+    }
+
+    Ok(())
+}
+
+/// Returns the pool a market is traded in, if it is live
+pub(crate) fn market_pool<'a>(state: &'a PoolState, market: &Market) -> Option<&'a Pool> {
+    match (state, market) {
+        (PoolState::Spot { spot }, Market::Spot) => Some(spot),
+        (PoolState::Futarchy { spot, .. }, Market::Spot) => Some(spot),
+        (PoolState::Futarchy { pass, .. }, Market::Pass) => Some(pass),
+        (PoolState::Futarchy { fail, .. }, Market::Fail) => Some(fail),
+        _ => None,
+    }
+}
+
+/// Rejects pools whose oracle was last updated more than `max_age` seconds ago
+fn check_oracle_age(pool: &Pool, now: i64, max_age: u32) -> ProgramResult {
+    let age = now.saturating_sub(pool.oracle.last_updated_timestamp);
+    if age > max_age as i64 {
+        msg!(format!("Stale oracle: {} > {}", age, max_age).as_str());
+        return Err(LobbyistError::StaleOracle.into());
+    }
+
+    Ok(())
+}
+
+/// Price of the pool of a market according to the price source of the escrow
+fn get_price(
+    pool: &Pool,
+    market: &Market,
+    escrow: &Escrow,
+    price_source: EscrowPriceSource,
+) -> ProgramResult<u128> {
+    match price_source {
+        EscrowPriceSource::WindowedTwap => get_windowed_twap(pool, market, escrow),
+        EscrowPriceSource::LifetimeTwap => get_twap(pool),
+        EscrowPriceSource::LastObservation => Ok(pool.oracle.last_observation),
+        EscrowPriceSource::Reserves => reserves_price(pool.base_reserves, pool.quote_reserves)
+            .ok_or(LobbyistError::MathOverflow.into()),
+    }
+}
+
+/// TWAP of the pool since the escrow snapshot of its market, or the lifetime TWAP without a
+/// usable one
+fn get_windowed_twap(pool: &Pool, market: &Market, escrow: &Escrow) -> ProgramResult<u128> {
+    let (last_aggregator, last_timestamp) = escrow.oracle_snapshot(market);
+    // Snapshots taken before the pool was created belong to a previous pool of the market
+    if last_timestamp == 0 || last_timestamp < pool.oracle.created_at_timestamp {
+        return get_twap(pool);
+    }
+
+    let seconds_passed = pool.oracle.last_updated_timestamp - last_timestamp;
+    if seconds_passed <= 0 {
+        // The oracle was not updated since, the observation held over the whole interval
+        return Ok(pool.oracle.last_observation);
+    }
+
+    // The aggregator rolls back to 0 on overflow
+    let aggregator = pool.oracle.aggregator.wrapping_sub(last_aggregator);
+
+    Ok(aggregator / seconds_passed as u128)
+}
+
+/// Oracle state of the pool of a market, so the next crank only looks at prices since this one
+fn pool_snapshot(market: Market, pool: &Pool) -> (Market, u128, i64) {
+    (
+        market,
+        pool.oracle.aggregator,
+        pool.oracle.last_updated_timestamp,
+    )
+}
+
 fn get_twap(pool: &Pool) -> ProgramResult<u128> {
     let start_timestamp = pool.oracle.created_at_timestamp + pool.oracle.start_delay_seconds as i64;
 
@@ -0,0 +1,183 @@
+use {
+    super::trade::{
+        apply_preferences, execute_swap, plan_trade, store_snapshots, EscrowAccounts, PlannedSwap,
+        Trade,
+    },
+    crate::{errors::LobbyistError, state::Escrow},
+    typhoon::prelude::*,
+    typhoon_token::{find_associated_token_address, TokenAccount},
+};
+
+/// Number of remaining accounts describing each escrow of a batch
+pub const BATCH_ESCROW_ACCOUNTS: usize = 7;
+
+/// Compute units kept for each escrow of a batch, a crank splitting and swapping through CPIs
+pub const CRANK_COMPUTE_UNITS: u64 = 120_000;
+
+#[cfg(target_os = "solana")]
+extern "C" {
+    fn sol_remaining_compute_units() -> u64;
+}
+
+/// Trades the context escrow, then each escrow listed in the remaining accounts as
+/// `[escrow, base, quote, pass base, pass quote, fail base, fail quote]` token account groups.
+///
+/// Escrows that cannot be traded are logged and skipped rather than failing the batch, which
+/// stops before the escrow whose crank might not fit in the remaining compute budget.
+pub fn trade_batch(ctx: Trade, remaining_accounts: Remaining) -> ProgramResult {
+    msg!("Trade batch");
+
+    if remaining_accounts.len() % BATCH_ESCROW_ACCOUNTS != 0 {
+        return Err(LobbyistError::InvalidRemainingAccounts.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+
+    trade_batched_escrow(&ctx, &EscrowAccounts::from_context(&ctx), now)?;
+
+    for (index, infos) in remaining_accounts
+        .chunks_exact(BATCH_ESCROW_ACCOUNTS)
+        .enumerate()
+    {
+        let compute_units = remaining_compute_units();
+        if compute_units < CRANK_COMPUTE_UNITS {
+            msg!(format!(
+                "Stopping batch before escrow {}: {} compute units left",
+                index, compute_units
+            )
+            .as_str());
+            break;
+        }
+
+        match EscrowGroup::load(&ctx, infos) {
+            Ok(group) => trade_batched_escrow(&ctx, &group.accounts(), now)?,
+            Err(error) => {
+                msg!(format!("Skipping escrow {:?}: {:?}", infos[0].key(), error).as_str())
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Trades an escrow of the batch, skipping it if any check fails before the first CPI
+fn trade_batched_escrow(ctx: &Trade, accounts: &EscrowAccounts, now: i64) -> ProgramResult {
+    msg!(format!("Escrow {:?}", accounts.escrow.key()).as_str());
+
+    let plan = match accounts
+        .check_backing()
+        .and_then(|_| apply_preferences(accounts, now))
+        .and_then(|_| plan_trade(ctx, &accounts.escrow.data()?, now))
+    {
+        Ok(plan) => plan,
+        Err(error) => {
+            msg!(format!("Skipping escrow: {:?}", error).as_str());
+            return Ok(());
+        }
+    };
+    store_snapshots(accounts, &plan.snapshots)?;
+
+    match plan.swap {
+        Some(PlannedSwap { params, .. }) => execute_swap(ctx, accounts, params, now)?,
+        None => msg!("Nothing to trade"),
+    }
+
+    accounts.check_backing()
+}
+
+/// Compute units left in the transaction budget
+#[cfg(target_os = "solana")]
+fn remaining_compute_units() -> u64 {
+    // SAFETY: the syscall takes no argument and only reads the meter
+    unsafe { sol_remaining_compute_units() }
+}
+
+#[cfg(not(target_os = "solana"))]
+fn remaining_compute_units() -> u64 {
+    u64::MAX
+}
+
+/// Escrow accounts loaded from a group of remaining accounts
+pub(crate) struct EscrowGroup<'info> {
+    escrow: Mut<Account<'info, Escrow>>,
+    escrow_base_ata: Mut<Account<'info, TokenAccount>>,
+    escrow_quote_ata: Mut<Account<'info, TokenAccount>>,
+    escrow_pass_base_ata: Mut<Account<'info, TokenAccount>>,
+    escrow_pass_quote_ata: Mut<Account<'info, TokenAccount>>,
+    escrow_fail_base_ata: Mut<Account<'info, TokenAccount>>,
+    escrow_fail_quote_ata: Mut<Account<'info, TokenAccount>>,
+}
+
+impl<'info> EscrowGroup<'info> {
+    /// Loads an escrow of the context proposal and its token accounts
+    pub(crate) fn load(ctx: &Trade, infos: &'info [AccountInfo]) -> ProgramResult<Self> {
+        let escrow = Mut::<Account<Escrow>>::try_from_info(&infos[0])?;
+        {
+            let data = escrow.data()?;
+            if data.dao != *ctx.dao.key() {
+                return Err(LobbyistError::InvalidDao.into());
+            }
+            if data.proposal != *ctx.proposal.key() {
+                return Err(LobbyistError::InvalidProposal.into());
+            }
+            if data.base_mint != *ctx.base_mint.key() {
+                return Err(LobbyistError::InvalidBaseMint.into());
+            }
+            if data.quote_mint != *ctx.quote_mint.key() {
+                return Err(LobbyistError::InvalidQuoteMint.into());
+            }
+        }
+
+        let key = *escrow.key();
+        Ok(Self {
+            escrow,
+            escrow_base_ata: escrow_token_account(&infos[1], &key, ctx.base_mint.key())?,
+            escrow_quote_ata: escrow_token_account(&infos[2], &key, ctx.quote_mint.key())?,
+            escrow_pass_base_ata: escrow_token_account(&infos[3], &key, ctx.pass_base_mint.key())?,
+            escrow_pass_quote_ata: escrow_token_account(
+                &infos[4],
+                &key,
+                ctx.pass_quote_mint.key(),
+            )?,
+            escrow_fail_base_ata: escrow_token_account(&infos[5], &key, ctx.fail_base_mint.key())?,
+            escrow_fail_quote_ata: escrow_token_account(
+                &infos[6],
+                &key,
+                ctx.fail_quote_mint.key(),
+            )?,
+        })
+    }
+
+    pub(crate) fn accounts(&self) -> EscrowAccounts<'_, 'info> {
+        EscrowAccounts {
+            escrow: &self.escrow,
+            escrow_base_ata: &self.escrow_base_ata,
+            escrow_quote_ata: &self.escrow_quote_ata,
+            escrow_pass_base_ata: &self.escrow_pass_base_ata,
+            escrow_pass_quote_ata: &self.escrow_pass_quote_ata,
+            escrow_fail_base_ata: &self.escrow_fail_base_ata,
+            escrow_fail_quote_ata: &self.escrow_fail_quote_ata,
+        }
+    }
+}
+
+/// Loads the associated token account of the escrow for the given mint
+fn escrow_token_account<'info>(
+    info: &'info AccountInfo,
+    escrow: &Pubkey,
+    mint: &Pubkey,
+) -> ProgramResult<Mut<Account<'info, TokenAccount>>> {
+    if *info.key() != find_associated_token_address(mint, escrow) {
+        return Err(LobbyistError::InvalidEscrowTokenAccount.into());
+    }
+
+    let token_account = Mut::<Account<TokenAccount>>::try_from_info(info)?;
+    {
+        let data = token_account.data()?;
+        if data.mint() != mint || data.owner() != escrow {
+            return Err(LobbyistError::InvalidEscrowTokenAccount.into());
+        }
+    }
+
+    Ok(token_account)
+}
@@ -0,0 +1,63 @@
+use {
+    super::initialize_escrow::validate_strategy,
+    crate::{
+        errors::LobbyistError,
+        state::{Escrow, EscrowStatus},
+        utils::{PodI16, PodU16},
+    },
+    bytemuck::{AnyBitPattern, NoUninit},
+    typhoon::prelude::*,
+};
+
+#[derive(Debug, PartialEq, AnyBitPattern, NoUninit, Copy, Clone)]
+#[repr(C)]
+pub struct UpdatePreferencesArgs {
+    pub bullish_threshold_bps: PodI16,
+    pub bearish_threshold_bps: PodI16,
+    pub strategy: u8,
+    pub market_making_band_bps: PodU16,
+}
+
+#[context]
+#[args(UpdatePreferencesArgs)]
+pub struct UpdatePreferences {
+    pub depositor: Signer,
+    #[constraint(
+        seeded,
+        bump = escrow.data_unchecked()?.bump,
+        has_one = depositor @ LobbyistError::InvalidDepositor,
+    )]
+    pub escrow: Mut<Account<Escrow>>,
+}
+
+/// Schedules new preferences for the escrow, taking effect once the preferences delay passed.
+///
+/// Replaces any update still pending.
+pub fn update_preferences(ctx: UpdatePreferences) -> ProgramResult {
+    msg!("Update preferences");
+
+    let strategy = validate_strategy(
+        ctx.args.strategy,
+        ctx.args.bullish_threshold_bps.into(),
+        ctx.args.bearish_threshold_bps.into(),
+        ctx.args.market_making_band_bps.into(),
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut escrow = ctx.escrow.mut_data()?;
+    if escrow.status == EscrowStatus::Done as u8 {
+        return Err(LobbyistError::EscrowAlreadySettled.into());
+    }
+
+    // A matured update is applied rather than overwritten
+    escrow.apply_pending_preferences(now);
+
+    escrow.pending_strategy = strategy as u8;
+    escrow.pending_bullish_threshold_bps = ctx.args.bullish_threshold_bps.into();
+    escrow.pending_bearish_threshold_bps = ctx.args.bearish_threshold_bps.into();
+    escrow.pending_market_making_band_bps = ctx.args.market_making_band_bps.into();
+    escrow.pending_preferences_timestamp = now + escrow.preferences_delay_seconds as i64;
+    msg!(format!("Effective at {}", escrow.pending_preferences_timestamp).as_str());
+
+    Ok(())
+}
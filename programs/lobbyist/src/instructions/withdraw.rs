@@ -1,5 +1,10 @@
 use {
-    crate::{futarchy_cpi::Proposal, state::Escrow, PodU64},
+    crate::{
+        errors::LobbyistError,
+        futarchy_cpi::{Market, Proposal},
+        state::{Escrow, EscrowStatus},
+        PodU64,
+    },
     bytemuck::{AnyBitPattern, NoUninit},
     typhoon::prelude::*,
     typhoon_token::{
@@ -57,6 +62,13 @@ pub struct Withdraw {
 pub fn withdraw(ctx: Withdraw) -> ProgramResult {
     msg!("Withdraw");
 
+    // Cranks could trade the withdrawn tokens from under an active escrow
+    match EscrowStatus::try_from(ctx.escrow.data()?.status)? {
+        EscrowStatus::Paused => {}
+        EscrowStatus::Active => return Err(LobbyistError::EscrowNotPaused.into()),
+        EscrowStatus::Done => return Err(LobbyistError::EscrowAlreadySettled.into()),
+    }
+
     let bump = [ctx.escrow.data_unchecked()?.bump as u8];
     let seeds = Escrow::derive_signer_seeds_with_bump(
         ctx.depositor.as_ref().key(),
@@ -87,6 +99,11 @@ pub fn withdraw(ctx: Withdraw) -> ProgramResult {
     let mut escrow = ctx.escrow.mut_data()?;
     escrow.base_amount -= ctx.args.base_amount;
     escrow.quote_amount -= ctx.args.quote_amount;
+    escrow.check_backing(
+        &Market::Spot,
+        ctx.escrow_base_ata.data()?.amount(),
+        ctx.escrow_quote_ata.data()?.amount(),
+    )?;
 
     Ok(())
 }
@@ -0,0 +1,171 @@
+use {
+    crate::{
+        errors::LobbyistError,
+        futarchy_cpi::{Market, Proposal},
+        state::{Escrow, EscrowStatus},
+        PodU64,
+    },
+    bytemuck::{AnyBitPattern, NoUninit},
+    typhoon::prelude::*,
+    typhoon_token::{spl_instructions::TransferChecked, Mint, TokenAccount, TokenProgram},
+};
+
+#[derive(Debug, PartialEq, AnyBitPattern, NoUninit, Copy, Clone)]
+#[repr(C)]
+pub struct WithdrawConditionalArgs {
+    pub pass_base_amount: PodU64,
+    pub pass_quote_amount: PodU64,
+    pub fail_base_amount: PodU64,
+    pub fail_quote_amount: PodU64,
+}
+
+#[context]
+#[args(WithdrawConditionalArgs)]
+pub struct WithdrawConditional {
+    pub depositor: Mut<Signer>,
+    #[constraint(
+        has_one = pass_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = pass_quote_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_base_mint @ LobbyistError::InvalidConditionalMint,
+        has_one = fail_quote_mint @ LobbyistError::InvalidConditionalMint,
+    )]
+    pub proposal: BorshAccount<Proposal>,
+    #[constraint(
+        seeded,
+        bump = escrow.data_unchecked()?.bump,
+        has_one = depositor @ LobbyistError::InvalidDepositor,
+        has_one = proposal @ LobbyistError::InvalidProposal,
+    )]
+    pub escrow: Mut<Account<Escrow>>,
+    pub pass_base_mint: Account<Mint>,
+    pub pass_quote_mint: Account<Mint>,
+    pub fail_base_mint: Account<Mint>,
+    pub fail_quote_mint: Account<Mint>,
+    #[constraint(
+        associated_token::mint = pass_base_mint,
+        associated_token::authority = depositor,
+    )]
+    pub user_pass_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_quote_mint,
+        associated_token::authority = depositor,
+    )]
+    pub user_pass_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_base_mint,
+        associated_token::authority = depositor,
+    )]
+    pub user_fail_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_quote_mint,
+        associated_token::authority = depositor,
+    )]
+    pub user_fail_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = pass_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_pass_quote_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_base_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_base_ata: Mut<Account<TokenAccount>>,
+    #[constraint(
+        associated_token::mint = fail_quote_mint,
+        associated_token::authority = escrow,
+    )]
+    pub escrow_fail_quote_ata: Mut<Account<TokenAccount>>,
+    pub token_program: Program<TokenProgram>,
+}
+
+/// Withdraws conditional tokens of the escrow so the depositor can trade them directly
+pub fn withdraw_conditional(ctx: WithdrawConditional) -> ProgramResult {
+    msg!("Withdraw conditional");
+
+    let escrow = *ctx.escrow.data()?;
+    match EscrowStatus::try_from(escrow.status)? {
+        EscrowStatus::Paused => {}
+        EscrowStatus::Active => return Err(LobbyistError::EscrowNotPaused.into()),
+        EscrowStatus::Done => return Err(LobbyistError::EscrowAlreadySettled.into()),
+    }
+
+    // Checked before any transfer, only tracked tokens can be withdrawn
+    let remaining = |tracked: u64, amount: PodU64| {
+        tracked
+            .checked_sub(amount.into())
+            .ok_or(LobbyistError::WithdrawalExceedsTrackedAmount)
+    };
+    let pass_base_amount = remaining(escrow.pass_base_amount, ctx.args.pass_base_amount)?;
+    let pass_quote_amount = remaining(escrow.pass_quote_amount, ctx.args.pass_quote_amount)?;
+    let fail_base_amount = remaining(escrow.fail_base_amount, ctx.args.fail_base_amount)?;
+    let fail_quote_amount = remaining(escrow.fail_quote_amount, ctx.args.fail_quote_amount)?;
+
+    let bump = [escrow.bump];
+    let seeds = Escrow::derive_signer_seeds_with_bump(&escrow.depositor, &escrow.proposal, &bump);
+
+    for (from, to, mint, amount) in [
+        (
+            &ctx.escrow_pass_base_ata,
+            &ctx.user_pass_base_ata,
+            &ctx.pass_base_mint,
+            ctx.args.pass_base_amount,
+        ),
+        (
+            &ctx.escrow_pass_quote_ata,
+            &ctx.user_pass_quote_ata,
+            &ctx.pass_quote_mint,
+            ctx.args.pass_quote_amount,
+        ),
+        (
+            &ctx.escrow_fail_base_ata,
+            &ctx.user_fail_base_ata,
+            &ctx.fail_base_mint,
+            ctx.args.fail_base_amount,
+        ),
+        (
+            &ctx.escrow_fail_quote_ata,
+            &ctx.user_fail_quote_ata,
+            &ctx.fail_quote_mint,
+            ctx.args.fail_quote_amount,
+        ),
+    ] {
+        let amount: u64 = amount.into();
+        if amount == 0 {
+            continue;
+        }
+
+        TransferChecked {
+            from: from.as_ref(),
+            mint: mint.as_ref(),
+            to: to.as_ref(),
+            authority: ctx.escrow.as_ref(),
+            amount,
+            decimals: mint.data()?.decimals(),
+        }
+        .invoke_signed(&[instruction::CpiSigner::from(&seeds)])?;
+    }
+
+    let mut escrow = ctx.escrow.mut_data()?;
+    escrow.pass_base_amount = pass_base_amount;
+    escrow.pass_quote_amount = pass_quote_amount;
+    escrow.fail_base_amount = fail_base_amount;
+    escrow.fail_quote_amount = fail_quote_amount;
+    escrow.check_backing(
+        &Market::Pass,
+        ctx.escrow_pass_base_ata.data()?.amount(),
+        ctx.escrow_pass_quote_ata.data()?.amount(),
+    )?;
+    escrow.check_backing(
+        &Market::Fail,
+        ctx.escrow_fail_base_ata.data()?.amount(),
+        ctx.escrow_fail_quote_ata.data()?.amount(),
+    )?;
+
+    Ok(())
+}
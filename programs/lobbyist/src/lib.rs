@@ -8,7 +8,7 @@ mod utils;
 
 use typhoon::macros::program_id;
 
-pub use {instructions::*, state::*, utils::*};
+pub use {errors::*, instructions::*, state::*, utils::*};
 
 program_id!("3JceRWanoEVZSqsY9UGtxPA4XsSAnSKDTNWp2Sp3QQLu");
 
@@ -20,4 +20,14 @@ handlers! {
     deposit,
     withdraw,
     trade,
+    merge,
+    settle_escrow,
+    quote_trade,
+    trade_batch,
+    cross_escrows,
+    set_status,
+    update_preferences,
+    close_escrow,
+    withdraw_conditional,
+    sync_escrow,
 }
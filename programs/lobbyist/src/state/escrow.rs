@@ -1,27 +1,105 @@
 use {
+    crate::{
+        errors::LobbyistError,
+        futarchy_cpi::{Market, SwapType},
+        utils::{PodU128, BPS_DENOMINATOR},
+    },
     bytemuck::{AnyBitPattern, NoUninit},
     typhoon::prelude::*,
 };
 
-// #[derive(Default, Copy, Clone, NoUninit, AnyBitPattern)]
-// #[repr(C)]
-// pub struct EscrowStatusId(u64);
-
-// impl From<EscrowStatus> for EscrowStatusId {
-//     fn from(status: EscrowStatus) -> Self {
-//         Self(status as u64)
-//     }
-// }
-
-#[derive(Default, Copy, Clone)]
+/// Lifecycle of an escrow, moved between Paused and Active by the depositor and to Done by
+/// settlement
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
 #[repr(u8)]
 pub enum EscrowStatus {
+    /// Deposits and withdrawals are open, cranks are refused
     #[default]
     Paused,
+    /// Cranks trade the escrow, withdrawals are refused
     Active,
+    /// Settled, nothing can happen anymore
     Done,
 }
 
+impl EscrowStatus {
+    /// Checks the escrow can move from this status to `status`
+    pub fn check_transition(self, status: EscrowStatus) -> Result<(), LobbyistError> {
+        match (self, status) {
+            (Self::Done, _) => Err(LobbyistError::EscrowAlreadySettled),
+            (Self::Active, Self::Active) => Err(LobbyistError::EscrowAlreadyActive),
+            (Self::Paused, Self::Paused) => Err(LobbyistError::EscrowAlreadyPaused),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl TryFrom<u8> for EscrowStatus {
+    type Error = LobbyistError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Paused),
+            1 => Ok(Self::Active),
+            2 => Ok(Self::Done),
+            _ => Err(LobbyistError::InvalidEscrowStatus),
+        }
+    }
+}
+
+/// How the escrow trades the decision markets
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum EscrowStrategy {
+    /// Pushes the fail market up and the pass market down
+    Bearish,
+    /// Pushes the pass market up
+    Bullish,
+    /// Trades both markets back into a band around spot
+    MarketMaking,
+}
+
+impl TryFrom<u8> for EscrowStrategy {
+    type Error = LobbyistError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Bearish),
+            1 => Ok(Self::Bullish),
+            2 => Ok(Self::MarketMaking),
+            _ => Err(LobbyistError::InvalidStrategy),
+        }
+    }
+}
+
+/// Price the escrow compares against its thresholds
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum EscrowPriceSource {
+    /// TWAP since the previous crank of the escrow
+    WindowedTwap,
+    /// TWAP since the pool was created
+    LifetimeTwap,
+    /// Last observation recorded by the pool oracle
+    LastObservation,
+    /// Instantaneous ratio of the pool reserves
+    Reserves,
+}
+
+impl TryFrom<u8> for EscrowPriceSource {
+    type Error = LobbyistError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::WindowedTwap),
+            1 => Ok(Self::LifetimeTwap),
+            2 => Ok(Self::LastObservation),
+            3 => Ok(Self::Reserves),
+            _ => Err(LobbyistError::InvalidPriceSource),
+        }
+    }
+}
+
 #[derive(NoUninit, AnyBitPattern, AccountState, Copy, Clone, Debug)]
 #[repr(C)]
 pub struct Escrow {
@@ -49,15 +127,189 @@ pub struct Escrow {
     pub fail_base_amount: u64,
     /// Amount of fail quote token owned by the escrow
     pub fail_quote_amount: u64,
-    /// Whether this escrow can trade
-    pub active: u8,
-    /// The trading preference of the escrow
-    pub bullish: u8,
+    /// Timestamp of the last trade made by the escrow
+    pub last_trade_timestamp: i64,
+    /// Timestamp at which the current trading window started
+    pub window_start_timestamp: i64,
+    /// Quote value traded during the current window
+    pub window_traded_amount: u64,
+    /// Maximum quote value traded per window, unlimited if 0
+    pub max_trade_per_window: u64,
+    /// Oracle update timestamps of the spot, pass and fail pools at the last crank reading them
+    pub last_aggregator_timestamps: [i64; 3],
+    /// Oracle aggregators of the spot, pass and fail pools at the last crank reading them
+    pub last_aggregators: [PodU128; 3],
+    /// Timestamp from which the pending preferences replace the current ones, none if 0
+    pub pending_preferences_timestamp: i64,
+    /// Length of a trading window
+    pub trade_window_seconds: u32,
+    /// Maximum age of the traded pool oracle, `MAXIMUM_AGE` if 0. Oracles only move on AMM
+    /// swaps, so a short age requires a swap in the traded market right before the crank
+    pub max_oracle_age_seconds: u32,
+    /// Trading stops this many seconds before the proposal ends
+    pub trade_cutoff_seconds: u32,
+    /// Delay before updated preferences take effect
+    pub preferences_delay_seconds: u32,
+    /// The [`EscrowStatus`] of the escrow
+    pub status: u8,
+    /// The [`EscrowStrategy`] of the escrow
+    pub strategy: u8,
     /// Will buy until the pass market price is this percentage of the spot price
     pub bullish_threshold_bps: i16,
-    /// Will sell until the fail market price is this percentage of the spot price
+    /// Will buy until the fail market price is this percentage of the spot price, then sell
+    /// until the pass market price is its inverse
     pub bearish_threshold_bps: i16,
+    /// Bullish threshold once the pending preferences take effect
+    pub pending_bullish_threshold_bps: i16,
+    /// Bearish threshold once the pending preferences take effect
+    pub pending_bearish_threshold_bps: i16,
+    /// Share of each swap input paid to the cranker, in the spot token of the input
+    pub cranker_fee_bps: u16,
+    /// Maximum deviation of a swap output from its quote at current reserves
+    pub max_slippage_bps: u16,
+    /// Share of the escrow value to hold in base tokens while no proposal is live, disabled if 0
+    pub spot_target_base_bps: u16,
+    /// Half width of the band around spot kept by market making escrows
+    pub market_making_band_bps: u16,
+    /// Market making band once the pending preferences take effect
+    pub pending_market_making_band_bps: u16,
+    /// The [`EscrowPriceSource`] of the escrow
+    pub price_source: u8,
+    /// Strategy once the pending preferences take effect
+    pub pending_strategy: u8,
     /// The canonical bump
     pub bump: u8,
-    pub _reserved: [u8; 9],
+    pub _reserved: [u8; 1],
+}
+
+impl Escrow {
+    /// Share of a swap input owed to the cranker of a trade
+    pub fn cranker_fee(&self, input_amount: u64) -> u64 {
+        (input_amount as u128 * self.cranker_fee_bps as u128 / BPS_DENOMINATOR) as u64
+    }
+
+    /// Returns the spot balance the cranker fee of a swap is paid from
+    pub fn fee_balance(&self, swap_type: &SwapType) -> u64 {
+        match swap_type {
+            SwapType::Buy => self.quote_amount,
+            SwapType::Sell => self.base_amount,
+        }
+    }
+
+    /// Returns the tracked (base, quote) balances held for the given market
+    pub fn market_amounts(&self, market: &Market) -> (u64, u64) {
+        match market {
+            Market::Spot => (self.base_amount, self.quote_amount),
+            Market::Pass => (self.pass_base_amount, self.pass_quote_amount),
+            Market::Fail => (self.fail_base_amount, self.fail_quote_amount),
+        }
+    }
+
+    /// Returns mutable references to the tracked (base, quote) balances of the given market
+    pub fn market_amounts_mut(&mut self, market: &Market) -> (&mut u64, &mut u64) {
+        match market {
+            Market::Spot => (&mut self.base_amount, &mut self.quote_amount),
+            Market::Pass => (&mut self.pass_base_amount, &mut self.pass_quote_amount),
+            Market::Fail => (&mut self.fail_base_amount, &mut self.fail_quote_amount),
+        }
+    }
+
+    /// Checks the tracked (base, quote) balances of a market are held by its token accounts
+    pub fn check_backing(
+        &self,
+        market: &Market,
+        base_balance: u64,
+        quote_balance: u64,
+    ) -> Result<(), LobbyistError> {
+        let (base_amount, quote_amount) = self.market_amounts(market);
+        if base_amount > base_balance || quote_amount > quote_balance {
+            return Err(LobbyistError::TrackedAmountExceedsBalance);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the amount of input tokens available to swap in a market, counting the spot
+    /// tokens that can be split into conditional tokens and keeping the cranker fee aside
+    pub fn input_available(&self, market: &Market, swap_type: &SwapType) -> u64 {
+        let spot_amount = self.fee_balance(swap_type);
+        let available = match (market, swap_type) {
+            (Market::Spot, _) => spot_amount,
+            (_, SwapType::Buy) => self.market_amounts(market).1 + spot_amount,
+            (_, SwapType::Sell) => self.market_amounts(market).0 + spot_amount,
+        };
+
+        // The fee is paid out of spot tokens before the swap input is split
+        available - self.cranker_fee(available).min(spot_amount)
+    }
+
+    /// Replaces the preferences by the pending ones once their delay passed, returns whether
+    /// they changed
+    pub fn apply_pending_preferences(&mut self, timestamp: i64) -> bool {
+        if self.pending_preferences_timestamp == 0 || timestamp < self.pending_preferences_timestamp
+        {
+            return false;
+        }
+
+        self.strategy = self.pending_strategy;
+        self.bullish_threshold_bps = self.pending_bullish_threshold_bps;
+        self.bearish_threshold_bps = self.pending_bearish_threshold_bps;
+        self.market_making_band_bps = self.pending_market_making_band_bps;
+        self.pending_preferences_timestamp = 0;
+
+        true
+    }
+
+    /// Returns the (aggregator, update timestamp) of the market oracle at the last crank
+    /// reading it, a null timestamp if none did
+    pub fn oracle_snapshot(&self, market: &Market) -> (u128, i64) {
+        let index = snapshot_index(market);
+        (
+            self.last_aggregators[index].into(),
+            self.last_aggregator_timestamps[index],
+        )
+    }
+
+    /// Stores the state of the market oracle read by a crank
+    pub fn set_oracle_snapshot(&mut self, market: &Market, aggregator: u128, timestamp: i64) {
+        let index = snapshot_index(market);
+        self.last_aggregators[index] = aggregator.into();
+        self.last_aggregator_timestamps[index] = timestamp;
+    }
+
+    /// Returns whether `timestamp` falls after the current trading window
+    fn window_elapsed(&self, timestamp: i64) -> bool {
+        timestamp >= self.window_start_timestamp + self.trade_window_seconds as i64
+    }
+
+    /// Returns the quote value the escrow can still trade at `timestamp`
+    pub fn window_budget(&self, timestamp: i64) -> u64 {
+        if self.max_trade_per_window == 0 {
+            u64::MAX
+        } else if self.window_elapsed(timestamp) {
+            self.max_trade_per_window
+        } else {
+            self.max_trade_per_window
+                .saturating_sub(self.window_traded_amount)
+        }
+    }
+
+    /// Records a trade of `quote_volume` quote value at `timestamp`
+    pub fn record_trade(&mut self, timestamp: i64, quote_volume: u64) {
+        if self.window_elapsed(timestamp) {
+            self.window_start_timestamp = timestamp;
+            self.window_traded_amount = 0;
+        }
+        self.window_traded_amount += quote_volume;
+        self.last_trade_timestamp = timestamp;
+    }
+}
+
+/// Position of a market in the oracle snapshots of an escrow
+fn snapshot_index(market: &Market) -> usize {
+    match market {
+        Market::Spot => 0,
+        Market::Pass => 1,
+        Market::Fail => 2,
+    }
 }
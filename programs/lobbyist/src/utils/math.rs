@@ -0,0 +1,66 @@
+//! Fixed-point helpers shared by the trading logic.
+
+/// Scale of the prices reported by the futarchy TWAP oracles
+pub const PRICE_SCALE: u128 = 1_000_000_000_000;
+
+/// Denominator of basis points values
+pub const BPS_DENOMINATOR: u128 = 10_000;
+
+/// Integer square root, rounded down
+pub fn sqrt_u128(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+
+    let mut x = 1u128 << ((128 - n.leading_zeros()).div_ceil(2));
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+/// Integer square root of `a * b`, rounded down, without overflowing on the product
+pub fn sqrt_mul(a: u128, b: u128) -> u128 {
+    let product = mul_wide(a, b);
+    if product.0 == 0 {
+        return sqrt_u128(product.1);
+    }
+
+    // The root does not fit the Newton iteration above, its bits are settled one by one
+    let mut root = 0u128;
+    for bit in (0..128).rev() {
+        let candidate = root | (1 << bit);
+        if mul_wide(candidate, candidate) <= product {
+            root = candidate;
+        }
+    }
+    root
+}
+
+/// Full 256-bit product of two `u128`, as its high and low halves
+fn mul_wide(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+
+    let (a_hi, a_lo) = (a >> 64, a & MASK);
+    let (b_hi, b_lo) = (b >> 64, b & MASK);
+    let lo_lo = a_lo * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_lo = a_hi * b_lo;
+    let hi_hi = a_hi * b_hi;
+
+    let mid = (lo_lo >> 64) + (lo_hi & MASK) + (hi_lo & MASK);
+    let lo = (lo_lo & MASK) | (mid << 64);
+    let hi = hi_hi + (lo_hi >> 64) + (hi_lo >> 64) + (mid >> 64);
+
+    (hi, lo)
+}
+
+/// Price of the base token in quote tokens implied by pool reserves, scaled by [`PRICE_SCALE`]
+pub fn reserves_price(base_reserves: u64, quote_reserves: u64) -> Option<u128> {
+    (quote_reserves as u128)
+        .checked_mul(PRICE_SCALE)?
+        .checked_div(base_reserves as u128)
+}
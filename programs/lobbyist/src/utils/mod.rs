@@ -1,5 +1,8 @@
 mod borsh;
+mod math;
 mod podbool;
 mod podint;
+mod proposal;
+mod sizing;
 
-pub use {borsh::*, podbool::*, podint::*};
+pub use {borsh::*, math::*, podbool::*, podint::*, proposal::*, sizing::*};
@@ -90,3 +90,4 @@ impl_podint!(PodI64, i64, 8);
 impl_podint!(PodU16, u16, 2);
 impl_podint!(PodU32, u32, 4);
 impl_podint!(PodU64, u64, 8);
+impl_podint!(PodU128, u128, 16);
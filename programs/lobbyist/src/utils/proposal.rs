@@ -0,0 +1,29 @@
+//! Lifecycle checks on futarchy proposals.
+
+use {
+    crate::{
+        errors::LobbyistError,
+        futarchy_cpi::{Proposal, ProposalState},
+    },
+    typhoon::prelude::*,
+};
+
+/// Timestamp at which the proposal can be finalized and its markets stop mattering
+pub fn trading_end_timestamp(proposal: &Proposal) -> i64 {
+    proposal.timestamp_enqueued + proposal.duration_in_seconds as i64
+}
+
+/// Rejects trades on proposals that are not pending or within `cutoff_seconds` of their end
+pub fn check_trading_open(proposal: &Proposal, now: i64, cutoff_seconds: u32) -> ProgramResult {
+    if !matches!(proposal.state, ProposalState::Pending) {
+        return Err(LobbyistError::ProposalNotPending.into());
+    }
+
+    let cutoff_timestamp = trading_end_timestamp(proposal) - cutoff_seconds as i64;
+    if now >= cutoff_timestamp {
+        msg!(format!("Trading ended: {} >= {}", now, cutoff_timestamp).as_str());
+        return Err(LobbyistError::ProposalTradingEnded.into());
+    }
+
+    Ok(())
+}
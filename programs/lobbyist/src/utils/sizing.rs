@@ -0,0 +1,238 @@
+//! Closed-form trade sizing against the constant-product pools of the futarchy AMM.
+//!
+//! A swap of `input` tokens adds `input * (1 - fee)` to the input reserve and keeps the
+//! product of the reserves constant, the fee being kept out of the pool. For a target
+//! price `p` (quote per base, scaled by [`PRICE_SCALE`]) and invariant `k`:
+//! - buying base moves the quote reserve to `sqrt(k * p)`,
+//! - selling base moves the base reserve to `sqrt(k / p)`.
+//!
+//! The products under the square roots are taken on 256 bits, so any pair of `u64` reserves
+//! and any target price can be sized. Every division and square root rounds down, and the
+//! resulting input is rounded down, so a sized trade always stops at or just before the
+//! target price, never past it.
+
+use crate::utils::{sqrt_mul, BPS_DENOMINATOR, PRICE_SCALE};
+
+/// Taker fee charged by the futarchy AMM on the input amount
+pub const AMM_FEE_BPS: u16 = 50;
+
+/// Square root of [`PRICE_SCALE`]
+const SQRT_PRICE_SCALE: u128 = 1_000_000;
+
+/// Quote input that buys base until the pool price rises to `target_price`.
+///
+/// Returns 0 when the pool price is already at or above the target, or the pool is empty.
+pub fn buy_input_to_target(
+    base_reserves: u64,
+    quote_reserves: u64,
+    target_price: u128,
+    fee_bps: u16,
+    balance: u64,
+) -> Option<u64> {
+    let k = base_reserves as u128 * quote_reserves as u128;
+    // sqrt(k * p / PRICE_SCALE), PRICE_SCALE being the square of SQRT_PRICE_SCALE
+    let target_quote_reserves = sqrt_mul(k, target_price) / SQRT_PRICE_SCALE;
+    let net_input = target_quote_reserves.saturating_sub(quote_reserves as u128);
+
+    gross_input(net_input, fee_bps, balance)
+}
+
+/// Base input that sells base until the pool price falls to `target_price`.
+///
+/// Returns `None` on a null target, and 0 when the pool price is already at or below the
+/// target, or the pool is empty.
+pub fn sell_input_to_target(
+    base_reserves: u64,
+    quote_reserves: u64,
+    target_price: u128,
+    fee_bps: u16,
+    balance: u64,
+) -> Option<u64> {
+    // sqrt(k * PRICE_SCALE / p), dividing the quote reserve first only rounds the root down
+    let scaled_quote_reserves = (quote_reserves as u128 * PRICE_SCALE).checked_div(target_price)?;
+    let target_base_reserves = sqrt_mul(base_reserves as u128, scaled_quote_reserves);
+    let net_input = target_base_reserves.saturating_sub(base_reserves as u128);
+
+    gross_input(net_input, fee_bps, balance)
+}
+
+/// Output of swapping `input` tokens into the pool at its current reserves, rounded down
+pub fn swap_output(
+    input_reserves: u64,
+    output_reserves: u64,
+    input: u64,
+    fee_bps: u16,
+) -> Option<u64> {
+    let net_input = (input as u128).checked_mul(BPS_DENOMINATOR.checked_sub(fee_bps as u128)?)?
+        / BPS_DENOMINATOR;
+    let output = (output_reserves as u128)
+        .checked_mul(net_input)?
+        .checked_div((input_reserves as u128).checked_add(net_input)?)?;
+
+    Some(output as u64)
+}
+
+/// Output of swapping `input` tokens at `price` net of the pool fee, rounded down, base for
+/// quote when buying and quote for base when selling.
+///
+/// Trades sized toward a target price fill at a better average price than the target, so the
+/// output at the target bounds theirs from below.
+pub fn output_at_price(input: u64, price: u128, fee_bps: u16, buy: bool) -> Option<u64> {
+    let net_input = (input as u128).checked_mul(BPS_DENOMINATOR.checked_sub(fee_bps as u128)?)?
+        / BPS_DENOMINATOR;
+    let output = if buy {
+        net_input.checked_mul(PRICE_SCALE)?.checked_div(price)?
+    } else {
+        net_input.checked_mul(price)? / PRICE_SCALE
+    };
+
+    u64::try_from(output).ok()
+}
+
+/// Smallest acceptable output given an expected output and a slippage tolerance, rounded up
+pub fn min_output(expected_output: u64, max_slippage_bps: u16) -> Option<u64> {
+    let min_output = (expected_output as u128)
+        .checked_mul(BPS_DENOMINATOR.checked_sub(max_slippage_bps as u128)?)?
+        .div_ceil(BPS_DENOMINATOR);
+
+    Some(min_output as u64)
+}
+
+/// Input amount whose post-fee part equals `net_input`, capped by the available balance
+fn gross_input(net_input: u128, fee_bps: u16, balance: u64) -> Option<u64> {
+    let input = net_input
+        .checked_mul(BPS_DENOMINATOR)?
+        .checked_div(BPS_DENOMINATOR.checked_sub(fee_bps as u128)?)?;
+
+    Some(input.min(balance as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::utils::reserves_price};
+
+    /// Pool price after swapping `input` into the pool, the fee staying out of the reserves
+    fn price_after(base_reserves: u64, quote_reserves: u64, input: u64, buy: bool) -> u128 {
+        let net_input =
+            (input as u128 * (BPS_DENOMINATOR - AMM_FEE_BPS as u128) / BPS_DENOMINATOR) as u64;
+        if buy {
+            let output = swap_output(quote_reserves, base_reserves, input, AMM_FEE_BPS).unwrap();
+            reserves_price(base_reserves - output, quote_reserves + net_input).unwrap()
+        } else {
+            let output = swap_output(base_reserves, quote_reserves, input, AMM_FEE_BPS).unwrap();
+            reserves_price(base_reserves + net_input, quote_reserves - output).unwrap()
+        }
+    }
+
+    #[test]
+    fn large_reserves() {
+        // The invariant of these pools overflows u128 once multiplied by a scaled price
+        for reserves in [1_000_000_000_000_000, 10_000_000_000_000_000_000] {
+            let price = reserves_price(reserves, reserves).unwrap();
+
+            let target_price = price * 11 / 10;
+            let input =
+                buy_input_to_target(reserves, reserves, target_price, AMM_FEE_BPS, u64::MAX)
+                    .unwrap();
+            let new_price = price_after(reserves, reserves, input, true);
+            assert!(new_price <= target_price);
+            assert!(new_price >= target_price * 9_999 / 10_000);
+
+            let target_price = price * 9 / 10;
+            let input =
+                sell_input_to_target(reserves, reserves, target_price, AMM_FEE_BPS, u64::MAX)
+                    .unwrap();
+            let new_price = price_after(reserves, reserves, input, false);
+            assert!(new_price >= target_price);
+            assert!(new_price <= target_price * 10_001 / 10_000);
+        }
+    }
+
+    #[test]
+    fn zero_reserves() {
+        assert_eq!(
+            buy_input_to_target(0, 0, PRICE_SCALE, AMM_FEE_BPS, u64::MAX),
+            Some(0)
+        );
+        assert_eq!(
+            sell_input_to_target(0, 0, PRICE_SCALE, AMM_FEE_BPS, u64::MAX),
+            Some(0)
+        );
+        assert_eq!(
+            sell_input_to_target(1_000_000, 1_000_000, 0, AMM_FEE_BPS, u64::MAX),
+            None
+        );
+    }
+
+    #[test]
+    fn target_reached() {
+        let reserves = 250_000_000;
+        let price = reserves_price(reserves, reserves).unwrap();
+
+        for target_price in [price, price / 2] {
+            assert_eq!(
+                buy_input_to_target(reserves, reserves, target_price, AMM_FEE_BPS, u64::MAX),
+                Some(0)
+            );
+        }
+        for target_price in [price, price * 2] {
+            assert_eq!(
+                sell_input_to_target(reserves, reserves, target_price, AMM_FEE_BPS, u64::MAX),
+                Some(0)
+            );
+        }
+    }
+
+    #[test]
+    fn capped_by_balance() {
+        let reserves = 250_000_000;
+        let target_price = reserves_price(reserves, reserves).unwrap() * 2;
+
+        assert_eq!(
+            buy_input_to_target(reserves, reserves, target_price, AMM_FEE_BPS, 1_000),
+            Some(1_000)
+        );
+    }
+
+    #[test]
+    fn sized_swaps_beat_target_output() {
+        let (base_reserves, quote_reserves) = (250_000_000, 250_000_000);
+        let target_price = PRICE_SCALE * 12 / 10;
+
+        let input = buy_input_to_target(
+            base_reserves,
+            quote_reserves,
+            target_price,
+            AMM_FEE_BPS,
+            u64::MAX,
+        )
+        .unwrap();
+        assert!(
+            swap_output(quote_reserves, base_reserves, input, AMM_FEE_BPS).unwrap()
+                >= output_at_price(input, target_price, AMM_FEE_BPS, true).unwrap()
+        );
+
+        let target_price = PRICE_SCALE * 8 / 10;
+        let input = sell_input_to_target(
+            base_reserves,
+            quote_reserves,
+            target_price,
+            AMM_FEE_BPS,
+            u64::MAX,
+        )
+        .unwrap();
+        assert!(
+            swap_output(base_reserves, quote_reserves, input, AMM_FEE_BPS).unwrap()
+                >= output_at_price(input, target_price, AMM_FEE_BPS, false).unwrap()
+        );
+        assert_eq!(output_at_price(1, 0, AMM_FEE_BPS, true), None);
+    }
+
+    #[test]
+    fn sqrt_of_wide_products() {
+        assert_eq!(sqrt_mul(u128::MAX, u128::MAX), u128::MAX);
+        assert_eq!(sqrt_mul(u128::MAX, 1), u64::MAX as u128);
+        assert_eq!(sqrt_mul(1 << 100, 1 << 100), 1 << 100);
+        assert_eq!(sqrt_mul((1 << 100) + 1, (1 << 100) - 1), (1 << 100) - 1);
+    }
+}
@@ -7,9 +7,11 @@ use {
             SQUADS_PROGRAM_CONFIG_TREASURY, SQUADS_PROGRAM_ID,
         },
     },
+    borsh::BorshDeserialize,
     litesvm::LiteSVM,
     lobbyist::futarchy_cpi::{
-        ConditionalSwapParams, InitializeDaoParams, Market, ProvideLiquidityParams, SwapType,
+        ConditionalSwapParams, Dao, InitializeDaoParams, Market, Proposal, ProposalState,
+        ProvideLiquidityParams, SpotSwapParams, SwapType,
     },
     solana_instruction::{AccountMeta, Instruction},
     solana_keypair::Keypair,
@@ -28,6 +30,8 @@ pub const CREATE_PROPOSAL_DISCRIMINATOR: &[u8] = &[50, 73, 156, 98, 129, 149, 21
 pub const LAUNCH_PROPOSAL_DISCRIMINATOR: &[u8] = &[16, 211, 189, 119, 245, 72, 0, 229];
 pub const PROVIDE_LIQUIDITY_DISCRIMINATOR: &[u8] = &[40, 110, 107, 116, 174, 127, 97, 204];
 pub const CONDITIONAL_SWAP_DISCRIMINATOR: &[u8] = &[194, 136, 220, 89, 242, 169, 130, 157];
+pub const SPOT_SWAP_DISCRIMINATOR: &[u8] = &[167, 97, 12, 231, 237, 78, 166, 251];
+pub const FINALIZE_PROPOSAL_DISCRIMINATOR: &[u8] = &[23, 68, 51, 167, 109, 173, 187, 164];
 
 pub const MIN_LP_TOKENS_LOCKED: u64 = 100;
 
@@ -63,6 +67,26 @@ pub fn amm_position_pda(dao_pda: Pubkey, position_authority: Pubkey) -> Pubkey {
     .0
 }
 
+pub fn get_dao(svm: &LiteSVM, dao_pda: Pubkey) -> Dao {
+    let dao_account = svm.get_account(&dao_pda).unwrap();
+    Dao::deserialize(&mut &dao_account.data[8..]).unwrap()
+}
+
+pub fn get_proposal(svm: &LiteSVM, proposal_pda: Pubkey) -> Proposal {
+    let proposal_account = svm.get_account(&proposal_pda).unwrap();
+    Proposal::deserialize(&mut &proposal_account.data[8..]).unwrap()
+}
+
+/// Overwrites the state of the proposal without going through its lifecycle
+pub fn set_proposal_state(svm: &mut LiteSVM, proposal_pda: Pubkey, state: ProposalState) {
+    let mut proposal_account = svm.get_account(&proposal_pda).unwrap();
+    let mut proposal = Proposal::deserialize(&mut &proposal_account.data[8..]).unwrap();
+    proposal.state = state;
+    let data = borsh::to_vec(&proposal).unwrap();
+    proposal_account.data[8..8 + data.len()].copy_from_slice(&data);
+    svm.set_account(proposal_pda, proposal_account).unwrap();
+}
+
 pub fn create_dao(
     svm: &mut LiteSVM,
     signer: &Keypair,
@@ -303,16 +327,16 @@ pub fn conditional_swap(
             get_associated_token_address(&trader.pubkey(), &pass_base_mint),
         ),
         (SwapType::Buy, Market::Fail) => (
-            get_associated_token_address(&trader.pubkey(), &pass_base_mint),
-            get_associated_token_address(&trader.pubkey(), &pass_quote_mint),
+            get_associated_token_address(&trader.pubkey(), &fail_quote_mint),
+            get_associated_token_address(&trader.pubkey(), &fail_base_mint),
         ),
         (SwapType::Sell, Market::Pass) => (
-            get_associated_token_address(&trader.pubkey(), &fail_base_mint),
-            get_associated_token_address(&trader.pubkey(), &fail_quote_mint),
+            get_associated_token_address(&trader.pubkey(), &pass_base_mint),
+            get_associated_token_address(&trader.pubkey(), &pass_quote_mint),
         ),
         (SwapType::Sell, Market::Fail) => (
-            get_associated_token_address(&trader.pubkey(), &fail_quote_mint),
             get_associated_token_address(&trader.pubkey(), &fail_base_mint),
+            get_associated_token_address(&trader.pubkey(), &fail_quote_mint),
         ),
         (_, Market::Spot) => unreachable!(),
     };
@@ -365,3 +389,118 @@ pub fn conditional_swap(
     );
     assert_tx!(svm.send_transaction(tx));
 }
+
+pub fn spot_swap(
+    svm: &mut LiteSVM,
+    trader: &Keypair,
+    dao_pda: Pubkey,
+    base_mint: Pubkey,
+    quote_mint: Pubkey,
+    params: SpotSwapParams,
+) {
+    let mut spot_swap_ix_data = SPOT_SWAP_DISCRIMINATOR.to_vec();
+    borsh::to_writer(&mut spot_swap_ix_data, &params).unwrap();
+    let spot_swap_ix = Instruction::new_with_bytes(
+        FUTARCHY_PROGRAM_ID,
+        &spot_swap_ix_data,
+        vec![
+            AccountMeta::new(dao_pda, false),
+            AccountMeta::new(
+                get_associated_token_address(&trader.pubkey(), &base_mint),
+                false,
+            ),
+            AccountMeta::new(
+                get_associated_token_address(&trader.pubkey(), &quote_mint),
+                false,
+            ),
+            AccountMeta::new(get_associated_token_address(&dao_pda, &base_mint), false),
+            AccountMeta::new(get_associated_token_address(&dao_pda, &quote_mint), false),
+            AccountMeta::new_readonly(trader.pubkey(), true),
+            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new_readonly(futarchy_event_authority_pda(), false),
+            AccountMeta::new_readonly(FUTARCHY_PROGRAM_ID, false),
+        ],
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[spot_swap_ix],
+        Some(&trader.pubkey()),
+        &[&trader],
+        svm.latest_blockhash(),
+    );
+    assert_tx!(svm.send_transaction(tx));
+}
+
+/// Finalizes the proposal on the TWAPs of its markets, which have to be past the proposal duration
+pub fn finalize_proposal(
+    svm: &mut LiteSVM,
+    signer: &Keypair,
+    dao_pda: Pubkey,
+    proposal: Pubkey,
+    question: Pubkey,
+    base_mint: Pubkey,
+    quote_mint: Pubkey,
+    base_vault: Pubkey,
+    quote_vault: Pubkey,
+    pass_base_mint: Pubkey,
+    pass_quote_mint: Pubkey,
+    fail_base_mint: Pubkey,
+    fail_quote_mint: Pubkey,
+) {
+    let squads_proposal = Pubkey::new_from_array(get_proposal(svm, proposal).squads_proposal);
+
+    let finalize_proposal_ix = Instruction::new_with_bytes(
+        FUTARCHY_PROGRAM_ID,
+        FINALIZE_PROPOSAL_DISCRIMINATOR,
+        vec![
+            AccountMeta::new(proposal, false),
+            AccountMeta::new(dao_pda, false),
+            AccountMeta::new(question, false),
+            AccountMeta::new(squads_proposal, false),
+            AccountMeta::new_readonly(squads_multisig_pda(dao_pda), false),
+            AccountMeta::new_readonly(SQUADS_PROGRAM_ID, false),
+            AccountMeta::new(
+                get_associated_token_address(&dao_pda, &pass_base_mint),
+                false,
+            ),
+            AccountMeta::new(
+                get_associated_token_address(&dao_pda, &pass_quote_mint),
+                false,
+            ),
+            AccountMeta::new(
+                get_associated_token_address(&dao_pda, &fail_base_mint),
+                false,
+            ),
+            AccountMeta::new(
+                get_associated_token_address(&dao_pda, &fail_quote_mint),
+                false,
+            ),
+            AccountMeta::new(get_associated_token_address(&dao_pda, &base_mint), false),
+            AccountMeta::new(get_associated_token_address(&dao_pda, &quote_mint), false),
+            AccountMeta::new_readonly(CONDITIONAL_VAULT_PROGRAM_ID, false),
+            AccountMeta::new_readonly(conditional_vault_event_authority_pda(), false),
+            AccountMeta::new_readonly(spl_token::ID, false),
+            AccountMeta::new(quote_vault, false),
+            AccountMeta::new(
+                get_associated_token_address(&quote_vault, &quote_mint),
+                false,
+            ),
+            AccountMeta::new(pass_quote_mint, false),
+            AccountMeta::new(fail_quote_mint, false),
+            AccountMeta::new(pass_base_mint, false),
+            AccountMeta::new(fail_base_mint, false),
+            AccountMeta::new(base_vault, false),
+            AccountMeta::new(get_associated_token_address(&base_vault, &base_mint), false),
+            AccountMeta::new_readonly(futarchy_event_authority_pda(), false),
+            AccountMeta::new_readonly(FUTARCHY_PROGRAM_ID, false),
+        ],
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[finalize_proposal_ix],
+        Some(&signer.pubkey()),
+        &[&signer],
+        svm.latest_blockhash(),
+    );
+    assert_tx!(svm.send_transaction(tx));
+}
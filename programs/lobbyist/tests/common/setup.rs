@@ -48,6 +48,13 @@ pub struct TestContext {
 
 impl TestContext {
     pub fn new(initial_supply: u64) -> TestContext {
+        let mut ctx = TestContext::new_draft(initial_supply);
+        ctx.launch();
+        ctx
+    }
+
+    /// Context whose proposal is not launched yet, the DAO pool trading spot only
+    pub fn new_draft(initial_supply: u64) -> TestContext {
         let mut svm = LiteSVM::new();
 
         // Add programs
@@ -143,19 +150,6 @@ impl TestContext {
             quote_vault_pda,
         );
 
-        launch_proposal(
-            &mut svm,
-            &signer,
-            dao_pda,
-            proposal_pda,
-            base_vault_pda,
-            quote_vault_pda,
-            pass_base_mint,
-            pass_quote_mint,
-            fail_base_mint,
-            fail_quote_mint,
-        );
-
         TestContext {
             svm,
             signer,
@@ -172,4 +166,19 @@ impl TestContext {
             fail_quote_mint,
         }
     }
+
+    pub fn launch(&mut self) {
+        launch_proposal(
+            &mut self.svm,
+            &self.signer,
+            self.dao,
+            self.proposal,
+            self.base_vault_pda,
+            self.quote_vault_pda,
+            self.pass_base_mint,
+            self.pass_quote_mint,
+            self.fail_base_mint,
+            self.fail_quote_mint,
+        );
+    }
 }
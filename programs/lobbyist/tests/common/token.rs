@@ -5,10 +5,15 @@ use {
     solana_pubkey::Pubkey,
     solana_signer::Signer,
     solana_transaction::Transaction,
-    spl_associated_token_account::instruction::create_associated_token_account,
+    spl_associated_token_account::{
+        get_associated_token_address,
+        instruction::{
+            create_associated_token_account, create_associated_token_account_idempotent,
+        },
+    },
     spl_token::{
-        instruction::{initialize_mint2, mint_to_checked},
-        state::Mint,
+        instruction::{initialize_account3, initialize_mint2, mint_to_checked, transfer_checked},
+        state::{Account, Mint},
     },
 };
 
@@ -65,3 +70,82 @@ pub fn create_token(svm: &mut LiteSVM, signer: &Keypair, initial_supply: u64) ->
 
     token_kp.pubkey()
 }
+
+/// Creates a token account of `owner` at a fresh address rather than the associated one
+pub fn create_token_account(
+    svm: &mut LiteSVM,
+    signer: &Keypair,
+    mint: Pubkey,
+    owner: Pubkey,
+) -> Pubkey {
+    let token_account_kp = Keypair::new();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &signer.pubkey(),
+                &token_account_kp.pubkey(),
+                svm.get_sysvar::<Rent>().minimum_balance(Account::LEN),
+                Account::LEN as u64,
+                &spl_token::ID,
+            ),
+            initialize_account3(&spl_token::ID, &token_account_kp.pubkey(), &mint, &owner).unwrap(),
+        ],
+        Some(&signer.pubkey()),
+        &[&signer, &token_account_kp],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).unwrap();
+
+    token_account_kp.pubkey()
+}
+
+/// Overwrites the balance of a token account, as if tokens had left it behind the program's back
+pub fn set_token_balance(svm: &mut LiteSVM, token_account: Pubkey, amount: u64) {
+    let mut account = svm.get_account(&token_account).unwrap();
+    let mut state = Account::unpack(&account.data).unwrap();
+    state.amount = amount;
+    Account::pack(state, &mut account.data).unwrap();
+    svm.set_account(token_account, account).unwrap();
+}
+
+/// Transfers tokens from the signer to the associated token account of `owner`, creating it
+pub fn transfer_tokens(
+    svm: &mut LiteSVM,
+    signer: &Keypair,
+    owner: Pubkey,
+    mint: Pubkey,
+    amount: u64,
+) {
+    let destination = get_associated_token_address(&owner, &mint);
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            create_associated_token_account_idempotent(
+                &signer.pubkey(),
+                &owner,
+                &mint,
+                &spl_token::ID,
+            ),
+            transfer_checked(
+                &spl_token::ID,
+                &get_associated_token_address(&signer.pubkey(), &mint),
+                &mint,
+                &destination,
+                &signer.pubkey(),
+                &[&signer.pubkey()],
+                amount,
+                6,
+            )
+            .unwrap(),
+        ],
+        Some(&signer.pubkey()),
+        &[&signer],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).unwrap();
+}
+
+pub fn token_balance(svm: &LiteSVM, token_account: Pubkey) -> u64 {
+    Account::unpack(&svm.get_account(&token_account).unwrap().data)
+        .unwrap()
+        .amount
+}
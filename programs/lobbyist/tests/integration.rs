@@ -1,19 +1,33 @@
 mod common;
 
 use {
-    crate::common::conditional_swap,
+    crate::common::{
+        conditional_swap, conditional_vault_event_authority_pda, create_token_account,
+        finalize_proposal, futarchy_event_authority_pda, get_dao, get_proposal, set_proposal_state,
+        set_token_balance, spot_swap, token_balance, transfer_tokens, CONDITIONAL_VAULT_PROGRAM_ID,
+        FUTARCHY_PROGRAM_ID,
+    },
     common::TestContext,
     lobbyist::{
-        futarchy_cpi::{ConditionalSwapParams, Market, SwapType},
+        futarchy_cpi::{
+            ConditionalSwapParams, Market, PoolState, ProposalState, SpotSwapParams, SwapType,
+        },
         *,
     },
-    solana_instruction::AccountMeta,
+    solana_instruction::{AccountMeta, Instruction},
+    solana_keypair::Keypair,
+    solana_native_token::LAMPORTS_PER_SOL,
     solana_program::clock::Clock,
     solana_pubkey::Pubkey,
     solana_sdk_ids::system_program,
     solana_signer::Signer,
     solana_transaction::Transaction,
-    spl_associated_token_account::get_associated_token_address,
+    spl_associated_token_account::{
+        get_associated_token_address,
+        instruction::{
+            create_associated_token_account, create_associated_token_account_idempotent,
+        },
+    },
     typhoon::lib::RefFromBytes,
     typhoon_instruction_builder::generate_instructions_client,
 };
@@ -25,118 +39,436 @@ fn integration_test() {
     let initial_supply = 1_000_000_000;
     let mut ctx = TestContext::new(initial_supply);
 
-    let escrow_pda = Pubkey::find_program_address(
-        &Escrow::derive(&ctx.signer.pubkey().to_bytes(), &ctx.proposal.to_bytes()),
-        &lobbyist::ID.into(),
-    )
-    .0;
+    let escrow_pda = setup_escrow(
+        &mut ctx,
+        InitializeEscrowArgs {
+            cranker_fee_bps: 10.into(),
+            max_trade_per_window: (initial_supply / 10).into(),
+            trade_window_seconds: 3600.into(),
+            ..default_args()
+        },
+        initial_supply / 2,
+        initial_supply / 2,
+    );
+    eprintln!("Escrow initialized and deposited");
 
     let escrow_base_ata = get_associated_token_address(&escrow_pda, &ctx.base_mint);
     let escrow_quote_ata = get_associated_token_address(&escrow_pda, &ctx.quote_mint);
-    let escrow_ix = InitializeEscrowInstruction {
-        ctx: InitializeEscrowContext {
-            depositor: ctx.signer.pubkey(),
+    let escrow_pass_base_ata = get_associated_token_address(&escrow_pda, &ctx.pass_base_mint);
+    let escrow_pass_quote_ata = get_associated_token_address(&escrow_pda, &ctx.pass_quote_mint);
+    let escrow_fail_base_ata = get_associated_token_address(&escrow_pda, &ctx.fail_base_mint);
+    let escrow_fail_quote_ata = get_associated_token_address(&escrow_pda, &ctx.fail_quote_mint);
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.base_amount, initial_supply / 2);
+    assert_eq!(escrow.quote_amount, initial_supply / 2);
+
+    let withdraw_ix =
+        withdraw_instruction(&ctx, escrow_pda, initial_supply / 4, initial_supply / 4);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[withdraw_ix],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+    eprintln!("Withdrew");
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.base_amount, initial_supply / 4);
+    assert_eq!(escrow.quote_amount, initial_supply / 4);
+
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+    eprintln!("Activated");
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 100);
+
+    let cranker = Keypair::new();
+    ctx.svm
+        .airdrop(&cranker.pubkey(), LAMPORTS_PER_SOL)
+        .unwrap();
+    let cranker_quote_ata = get_associated_token_address(&cranker.pubkey(), &ctx.quote_mint);
+    let tx = Transaction::new_signed_with_payer(
+        &[ctx.base_mint, ctx.quote_mint].map(|mint| {
+            create_associated_token_account(
+                &cranker.pubkey(),
+                &cranker.pubkey(),
+                &mint,
+                &spl_token::ID,
+            )
+        }),
+        Some(&cranker.pubkey()),
+        &[&cranker],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    let mut trade_ix = TradeInstruction {
+        ctx: trade_context(&ctx, escrow_pda, cranker.pubkey()),
+    }
+    .into_instruction();
+
+    trade_ix.accounts.extend([
+        AccountMeta::new_readonly(ctx.signer.pubkey(), false),
+        AccountMeta::new_readonly(ctx.signer.pubkey(), false),
+    ]);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[trade_ix],
+        Some(&cranker.pubkey()),
+        &[&cranker],
+        ctx.svm.latest_blockhash(),
+    );
+    let res = assert_tx!(ctx.svm.send_transaction(tx));
+    eprintln!("{}", res.logs.join("\n"));
+    eprintln!("Trade executed");
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.pass_base_amount > 0);
+    assert!(escrow.fail_quote_amount > 0);
+    assert!(escrow.quote_amount < initial_supply / 4);
+    // The fee was set aside before the input was split
+    assert!(token_balance(&ctx.svm, cranker_quote_ata) > 0);
+
+    let merge_ix = MergeInstruction {
+        ctx: MergeContext {
             proposal: ctx.proposal,
             escrow: escrow_pda,
-            dao: ctx.dao,
             base_mint: ctx.base_mint,
             quote_mint: ctx.quote_mint,
+            pass_base_mint: ctx.pass_base_mint,
+            pass_quote_mint: ctx.pass_quote_mint,
+            fail_base_mint: ctx.fail_base_mint,
+            fail_quote_mint: ctx.fail_quote_mint,
             escrow_base_ata,
             escrow_quote_ata,
+            escrow_pass_base_ata,
+            escrow_pass_quote_ata,
+            escrow_fail_base_ata,
+            escrow_fail_quote_ata,
+            base_vault: ctx.base_vault_pda,
+            base_vault_underlying_token_account: get_associated_token_address(
+                &ctx.base_vault_pda,
+                &ctx.base_mint,
+            ),
+            quote_vault: ctx.quote_vault_pda,
+            quote_vault_underlying_token_account: get_associated_token_address(
+                &ctx.quote_vault_pda,
+                &ctx.quote_mint,
+            ),
+            question: ctx.question,
+            vault_event_authority: conditional_vault_event_authority_pda(),
+            conditional_vault_program: CONDITIONAL_VAULT_PROGRAM_ID,
             token_program: spl_token::ID.into(),
-            ata_token_program: spl_associated_token_account::ID.into(),
-            system_program: system_program::id(),
-            args: InitializeEscrowArgs {
-                bullish_threshold_bps: 10000.into(),
-                bearish_threshold_bps: 10000.into(),
-                bullish: true.into(),
-            },
         },
     }
     .into_instruction();
 
     let tx = Transaction::new_signed_with_payer(
-        &[escrow_ix],
+        &[merge_ix],
+        Some(&cranker.pubkey()),
+        &[&cranker],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+    eprintln!("Merged");
+
+    let quote_amount = escrow.quote_amount;
+    let merged_amount = escrow.pass_quote_amount.min(escrow.fail_quote_amount);
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.quote_amount, quote_amount + merged_amount);
+    assert_eq!(escrow.pass_quote_amount.min(escrow.fail_quote_amount), 0);
+
+    // The depositor takes the conditional position out
+    let mut ixs = vec![set_status_instruction(
+        ctx.signer.pubkey(),
+        escrow_pda,
+        EscrowStatus::Paused,
+    )];
+    for mint in conditional_mints(&ctx) {
+        ixs.push(create_associated_token_account_idempotent(
+            &ctx.signer.pubkey(),
+            &ctx.signer.pubkey(),
+            &mint,
+            &spl_token::ID,
+        ));
+    }
+    ixs.push(withdraw_conditional_instruction(
+        &ctx,
+        escrow_pda,
+        conditional_mints(&ctx),
+        WithdrawConditionalArgs {
+            pass_base_amount: escrow.pass_base_amount.into(),
+            pass_quote_amount: escrow.pass_quote_amount.into(),
+            fail_base_amount: escrow.fail_base_amount.into(),
+            fail_quote_amount: escrow.fail_quote_amount.into(),
+        },
+    ));
+
+    let tx = Transaction::new_signed_with_payer(
+        &ixs,
         Some(&ctx.signer.pubkey()),
         &[&ctx.signer],
         ctx.svm.latest_blockhash(),
     );
     assert_tx!(ctx.svm.send_transaction(tx));
-    eprintln!("Escrow initialized");
+    eprintln!("Withdrew conditional tokens");
 
-    let user_base_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.base_mint);
-    let user_quote_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.quote_mint);
-    let deposit_ix = DepositInstruction {
-        ctx: DepositContext {
-            depositor: ctx.signer.pubkey(),
-            escrow: escrow_pda,
-            base_mint: ctx.base_mint,
-            quote_mint: ctx.quote_mint,
-            user_base_ata,
-            user_quote_ata,
-            escrow_base_ata,
-            escrow_quote_ata,
-            token_program: spl_token::ID.into(),
-            system_program: system_program::id(),
-            args: DepositArgs {
-                base_amount: (initial_supply / 2).into(),
-                quote_amount: (initial_supply / 2).into(),
-            },
-        },
-    }
-    .into_instruction();
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.pass_base_amount, 0);
+    assert_eq!(escrow.pass_quote_amount, 0);
+    assert_eq!(escrow.fail_base_amount, 0);
+    assert_eq!(escrow.fail_quote_amount, 0);
+}
+
+#[test]
+fn withdraw_conditional_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
 
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), 0, initial_supply / 100);
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+    crank(&mut ctx, escrow_pda);
+
+    let ixs = conditional_mints(&ctx).map(|mint| {
+        create_associated_token_account_idempotent(
+            &ctx.signer.pubkey(),
+            &ctx.signer.pubkey(),
+            &mint,
+            &spl_token::ID,
+        )
+    });
     let tx = Transaction::new_signed_with_payer(
-        &[deposit_ix],
+        &ixs,
         Some(&ctx.signer.pubkey()),
         &[&ctx.signer],
         ctx.svm.latest_blockhash(),
     );
     assert_tx!(ctx.svm.send_transaction(tx));
-    eprintln!("Deposited");
 
     let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
     let escrow = Escrow::read(&escrow_account.data).unwrap();
-    assert_eq!(escrow.base_amount, initial_supply / 2);
-    assert_eq!(escrow.quote_amount, initial_supply / 2);
+    assert!(escrow.pass_base_amount > 0);
+    let args = WithdrawConditionalArgs {
+        pass_base_amount: (escrow.pass_base_amount / 2).into(),
+        pass_quote_amount: 0.into(),
+        fail_base_amount: 0.into(),
+        fail_quote_amount: escrow.fail_quote_amount.into(),
+    };
 
-    let withdraw_ix = WithdrawInstruction {
-        ctx: WithdrawContext {
-            depositor: ctx.signer.pubkey(),
-            proposal: ctx.proposal,
-            escrow: escrow_pda,
-            base_mint: ctx.base_mint,
-            quote_mint: ctx.quote_mint,
-            user_base_ata,
-            user_quote_ata,
-            escrow_base_ata,
-            escrow_quote_ata,
-            token_program: spl_token::ID.into(),
-            ata_token_program: spl_associated_token_account::ID.into(),
-            system_program: system_program::id(),
-            args: WithdrawArgs {
-                base_amount: (initial_supply / 4).into(),
-                quote_amount: (initial_supply / 4).into(),
+    assert_error(
+        &mut ctx,
+        withdraw_conditional_instruction(&ctx, escrow_pda, conditional_mints(&ctx), args),
+        LobbyistError::EscrowNotPaused,
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[set_status_instruction(
+            ctx.signer.pubkey(),
+            escrow_pda,
+            EscrowStatus::Paused,
+        )],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    // Conditional mints of the proposal only, in their own slots
+    let [pass_base_mint, pass_quote_mint, fail_base_mint, fail_quote_mint] =
+        conditional_mints(&ctx);
+    assert_error(
+        &mut ctx,
+        withdraw_conditional_instruction(
+            &ctx,
+            escrow_pda,
+            [
+                fail_base_mint,
+                pass_quote_mint,
+                pass_base_mint,
+                fail_quote_mint,
+            ],
+            args,
+        ),
+        LobbyistError::InvalidConditionalMint,
+    );
+
+    assert_error(
+        &mut ctx,
+        withdraw_conditional_instruction(
+            &ctx,
+            escrow_pda,
+            conditional_mints(&ctx),
+            WithdrawConditionalArgs {
+                pass_base_amount: (escrow.pass_base_amount + 1).into(),
+                ..args
             },
+        ),
+        LobbyistError::WithdrawalExceedsTrackedAmount,
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[withdraw_conditional_instruction(
+            &ctx,
+            escrow_pda,
+            conditional_mints(&ctx),
+            args,
+        )],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    let user_pass_base_ata = get_associated_token_address(&ctx.signer.pubkey(), &pass_base_mint);
+    assert_eq!(
+        token_balance(&ctx.svm, user_pass_base_ata),
+        escrow.pass_base_amount / 2
+    );
+    let escrow_after = Escrow::read(&ctx.svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(
+        escrow_after.pass_base_amount,
+        escrow.pass_base_amount - escrow.pass_base_amount / 2
+    );
+    assert_eq!(escrow_after.fail_quote_amount, 0);
+
+    finalize(&mut ctx, true);
+    let tx = Transaction::new_signed_with_payer(
+        &[settle_instruction(&ctx, escrow_pda, ctx.signer.pubkey())],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    assert_error(
+        &mut ctx,
+        withdraw_conditional_instruction(&ctx, escrow_pda, conditional_mints(&ctx), args),
+        LobbyistError::EscrowAlreadySettled,
+    );
+}
+
+#[test]
+fn bearish_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let escrow_pda = setup_escrow(
+        &mut ctx,
+        InitializeEscrowArgs {
+            bullish_threshold_bps: 0.into(),
+            bearish_threshold_bps: 11000.into(),
+            strategy: EscrowStrategy::Bearish as u8,
+            price_source: EscrowPriceSource::Reserves as u8,
+            ..default_args()
         },
-    }
-    .into_instruction();
+        initial_supply / 2,
+        initial_supply / 2,
+    );
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    // Someone bets on the proposal passing by dumping fail tokens
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Fail, SwapType::Sell, initial_supply / 100);
+
+    let PoolState::Futarchy { fail, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are not live");
+    };
+    let fail_price_before = reserves_price(fail.base_reserves, fail.quote_reserves).unwrap();
+
+    let quote = quote_trade(&mut ctx, escrow_pda);
+    assert!(bool::from(quote.trade));
+    assert_eq!(quote.market, Market::Fail as u8);
+    assert_eq!(quote.swap_type, SwapType::Buy as u8);
+    assert!(u64::from(quote.expected_output_amount) >= u64::from(quote.min_output_amount));
+    assert_eq!(u64::from(quote.cranker_fee), 0);
+
+    crank(&mut ctx, escrow_pda);
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.fail_base_amount >= u64::from(quote.min_output_amount));
+    assert!(escrow.pass_quote_amount > 0);
+    assert_eq!(escrow.pass_base_amount, 0);
+    assert!(escrow.quote_amount < initial_supply / 2);
 
+    let PoolState::Futarchy { fail, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are not live");
+    };
+    let fail_price = reserves_price(fail.base_reserves, fail.quote_reserves).unwrap();
+    assert!(fail_price > fail_price_before);
+
+    // The bet pays off once the proposal fails
+    finalize(&mut ctx, false);
+    let user_base_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.base_mint);
+    let base_before = token_balance(&ctx.svm, user_base_ata);
     let tx = Transaction::new_signed_with_payer(
-        &[withdraw_ix],
+        &[settle_instruction(&ctx, escrow_pda, ctx.signer.pubkey())],
         Some(&ctx.signer.pubkey()),
         &[&ctx.signer],
         ctx.svm.latest_blockhash(),
     );
     assert_tx!(ctx.svm.send_transaction(tx));
-    eprintln!("Withdrew");
 
+    assert_eq!(
+        token_balance(&ctx.svm, user_base_ata),
+        base_before + escrow.base_amount + escrow.fail_base_amount
+    );
     let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
     let escrow = Escrow::read(&escrow_account.data).unwrap();
-    assert_eq!(escrow.base_amount, initial_supply / 4);
-    assert_eq!(escrow.quote_amount, initial_supply / 4);
+    assert_eq!(escrow.status, EscrowStatus::Done as u8);
+}
+
+#[test]
+fn bearish_base_only_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    // Without quote tokens the escrow cannot buy the fail market
+    let escrow_pda = setup_escrow(
+        &mut ctx,
+        InitializeEscrowArgs {
+            bullish_threshold_bps: 0.into(),
+            bearish_threshold_bps: 11000.into(),
+            strategy: EscrowStrategy::Bearish as u8,
+            ..default_args()
+        },
+        initial_supply / 10,
+        0,
+    );
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+    swap_market(&mut ctx, Market::Fail, SwapType::Buy, initial_supply / 1000);
+
+    // It sells the pass market down instead
+    let quote = quote_trade(&mut ctx, escrow_pda);
+    assert!(bool::from(quote.trade));
+    assert_eq!(quote.market, Market::Pass as u8);
+    assert_eq!(quote.swap_type, SwapType::Sell as u8);
+    assert!(u64::from(quote.input_amount) > 0);
+}
+
+#[test]
+fn sizing_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
 
-    eprintln!("Time: {}", ctx.svm.get_sysvar::<Clock>().unix_timestamp);
     ctx.svm.set_sysvar::<Clock>(&Clock {
         unix_timestamp: 88000,
         epoch_start_timestamp: 100,
@@ -144,7 +476,19 @@ fn integration_test() {
         leader_schedule_epoch: 100,
         slot: 100,
     });
-    eprintln!("Time: {}", ctx.svm.get_sysvar::<Clock>().unix_timestamp);
+
+    let PoolState::Futarchy { pass, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are not live");
+    };
+    let target_price = reserves_price(pass.base_reserves, pass.quote_reserves).unwrap() * 11 / 10;
+    let input_amount = buy_input_to_target(
+        pass.base_reserves,
+        pass.quote_reserves,
+        target_price,
+        AMM_FEE_BPS,
+        u64::MAX,
+    )
+    .unwrap();
 
     conditional_swap(
         &mut ctx.svm,
@@ -162,41 +506,1977 @@ fn integration_test() {
         ctx.fail_quote_mint,
         ConditionalSwapParams {
             swap_type: SwapType::Buy,
-            input_amount: initial_supply / 100,
+            input_amount,
             min_output_amount: 0,
             market: Market::Pass,
         },
     );
 
-    let mut trade_ix = TradeInstruction {
-        ctx: TradeContext {
-            depositor: ctx.signer.pubkey(),
-            dao: ctx.dao,
-            proposal: ctx.proposal,
-            escrow: escrow_pda,
-            base_mint: ctx.base_mint,
-            quote_mint: ctx.quote_mint,
-            escrow_base_ata,
-            escrow_quote_ata,
-            token_program: spl_token::ID.into(),
-            ata_token_program: spl_associated_token_account::ID.into(),
-            system_program: system_program::id(),
+    let PoolState::Futarchy { pass, fail, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are not live");
+    };
+    let pass_price = reserves_price(pass.base_reserves, pass.quote_reserves).unwrap();
+    assert!(pass_price <= target_price);
+    assert!(pass_price >= target_price * 9_999 / 10_000);
+
+    let target_price = reserves_price(fail.base_reserves, fail.quote_reserves).unwrap() * 9 / 10;
+    let input_amount = sell_input_to_target(
+        fail.base_reserves,
+        fail.quote_reserves,
+        target_price,
+        AMM_FEE_BPS,
+        u64::MAX,
+    )
+    .unwrap();
+
+    conditional_swap(
+        &mut ctx.svm,
+        &ctx.signer,
+        ctx.dao,
+        ctx.proposal,
+        ctx.question,
+        ctx.base_mint,
+        ctx.quote_mint,
+        ctx.base_vault_pda,
+        ctx.quote_vault_pda,
+        ctx.pass_base_mint,
+        ctx.pass_quote_mint,
+        ctx.fail_base_mint,
+        ctx.fail_quote_mint,
+        ConditionalSwapParams {
+            swap_type: SwapType::Sell,
+            input_amount,
+            min_output_amount: 0,
+            market: Market::Fail,
         },
-    }
-    .into_instruction();
+    );
 
-    trade_ix.accounts.extend([
-        AccountMeta::new_readonly(ctx.signer.pubkey(), false),
-        AccountMeta::new_readonly(ctx.signer.pubkey(), false),
-    ]);
+    let PoolState::Futarchy { fail, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are not live");
+    };
+    let fail_price = reserves_price(fail.base_reserves, fail.quote_reserves).unwrap();
+    assert!(fail_price >= target_price);
+    assert!(fail_price <= target_price * 10_001 / 10_000);
+}
 
-    let tx = Transaction::new_signed_with_payer(
-        &[trade_ix],
-        Some(&ctx.signer.pubkey()),
-        &[&ctx.signer],
-        ctx.svm.latest_blockhash(),
+#[test]
+fn market_making_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    // Keeps both markets within 5% of spot, judged on their reserves
+    let escrow_pda = setup_escrow(
+        &mut ctx,
+        InitializeEscrowArgs {
+            bullish_threshold_bps: 0.into(),
+            strategy: EscrowStrategy::MarketMaking as u8,
+            market_making_band_bps: 500.into(),
+            price_source: EscrowPriceSource::Reserves as u8,
+            ..default_args()
+        },
+        initial_supply / 100,
+        initial_supply / 100,
     );
-    let res = assert_tx!(ctx.svm.send_transaction(tx));
-    eprintln!("{}", res.logs.join("\n"));
-    eprintln!("Trade executed");
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, 1000);
+    swap_market(&mut ctx, Market::Fail, SwapType::Buy, 1000);
+
+    // Inside the band
+    let quote = quote_trade(&mut ctx, escrow_pda);
+    assert!(!bool::from(quote.trade));
+
+    // Below the band, the fail market is bought back to its lower edge
+    swap_market(&mut ctx, Market::Fail, SwapType::Sell, initial_supply / 100);
+    let quote = quote_trade(&mut ctx, escrow_pda);
+    assert!(bool::from(quote.trade));
+    assert_eq!(quote.market, Market::Fail as u8);
+    assert_eq!(quote.swap_type, SwapType::Buy as u8);
+    crank(&mut ctx, escrow_pda);
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.fail_base_amount > escrow.pass_base_amount);
+    let PoolState::Futarchy { fail, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are not live");
+    };
+    let fail_price = reserves_price(fail.base_reserves, fail.quote_reserves).unwrap();
+    assert!(fail_price <= PRICE_SCALE * 95 / 100);
+    assert!(fail_price >= PRICE_SCALE * 9499 / 10_000);
+
+    // Above the band, the pass market is sold back to its upper edge
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 100);
+    let quote = quote_trade(&mut ctx, escrow_pda);
+    assert!(bool::from(quote.trade));
+    assert_eq!(quote.market, Market::Pass as u8);
+    assert_eq!(quote.swap_type, SwapType::Sell as u8);
+    crank(&mut ctx, escrow_pda);
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.pass_quote_amount > escrow.fail_quote_amount);
+    let PoolState::Futarchy { pass, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are not live");
+    };
+    let pass_price = reserves_price(pass.base_reserves, pass.quote_reserves).unwrap();
+    assert!(pass_price >= PRICE_SCALE * 105 / 100);
+    assert!(pass_price <= PRICE_SCALE * 10_501 / 10_000);
+}
+
+#[test]
+fn cross_escrows_test() {
+    for cranker_fee_bps in [0, MAX_CRANKER_FEE_BPS] {
+        let initial_supply = 1_000_000_000;
+        let mut ctx = TestContext::new(initial_supply);
+
+        let amount = initial_supply / 1000;
+        let buyer_args = InitializeEscrowArgs {
+            cranker_fee_bps: cranker_fee_bps.into(),
+            ..default_args()
+        };
+        let bearish_args = InitializeEscrowArgs {
+            bullish_threshold_bps: 0.into(),
+            bearish_threshold_bps: 12000.into(),
+            strategy: EscrowStrategy::Bearish as u8,
+            ..buyer_args
+        };
+        // Buys the pass market
+        let buyer_escrow = setup_escrow(&mut ctx, buyer_args, 0, 2 * amount);
+        let signer = ctx.signer.insecure_clone();
+        activate_escrow(&mut ctx, &signer);
+        // Holding no quote tokens, sells the pass market
+        let seller = create_depositor(&mut ctx, amount, 0);
+        let seller_escrow = setup_escrow_for(&mut ctx, &seller, bearish_args, amount, 0);
+        activate_escrow(&mut ctx, &seller);
+        // Buys the pass market too
+        let other_buyer = create_depositor(&mut ctx, 0, amount);
+        let other_buyer_escrow =
+            setup_escrow_for(&mut ctx, &other_buyer, default_args(), 0, amount);
+        activate_escrow(&mut ctx, &other_buyer);
+        // Sells the fail market once it is above its band
+        let market_maker = create_depositor(&mut ctx, amount, amount);
+        let market_maker_escrow = setup_escrow_for(
+            &mut ctx,
+            &market_maker,
+            InitializeEscrowArgs {
+                bullish_threshold_bps: 0.into(),
+                strategy: EscrowStrategy::MarketMaking as u8,
+                market_making_band_bps: 500.into(),
+                price_source: EscrowPriceSource::Reserves as u8,
+                ..default_args()
+            },
+            amount,
+            amount,
+        );
+        activate_escrow(&mut ctx, &market_maker);
+        // Left paused
+        let paused = create_depositor(&mut ctx, amount, 0);
+        let paused_escrow = setup_escrow_for(&mut ctx, &paused, bearish_args, amount, 0);
+
+        set_timestamp(&mut ctx, 80000);
+        swap_market(&mut ctx, Market::Pass, SwapType::Buy, 1000);
+        swap_market(&mut ctx, Market::Fail, SwapType::Buy, initial_supply / 100);
+
+        for (second, error) in [
+            (other_buyer_escrow, LobbyistError::EscrowsNotCrossable),
+            (market_maker_escrow, LobbyistError::EscrowsNotCrossable),
+            (paused_escrow, LobbyistError::EscrowNotActive),
+        ] {
+            assert_error(
+                &mut ctx,
+                cross_instruction(&ctx, buyer_escrow, second),
+                error,
+            );
+        }
+
+        let buy = quote_trade(&mut ctx, buyer_escrow);
+        let sell = quote_trade(&mut ctx, seller_escrow);
+        assert_eq!(
+            (buy.market, buy.swap_type),
+            (Market::Pass as u8, SwapType::Buy as u8)
+        );
+        assert_eq!(
+            (sell.market, sell.swap_type),
+            (Market::Pass as u8, SwapType::Sell as u8)
+        );
+
+        // The seller side is matched in full at the mid price
+        let PoolState::Futarchy { pass, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+            panic!("Markets are not live");
+        };
+        let price = reserves_price(pass.base_reserves, pass.quote_reserves).unwrap();
+        let sell_input = u64::from(sell.input_amount);
+        let quote_amount = (sell_input as u128 * price / PRICE_SCALE) as u64;
+        let base_amount = (quote_amount as u128 * PRICE_SCALE / price) as u64;
+        let buy_residual = u64::from(buy.input_amount) - quote_amount;
+        let sell_residual = sell_input - base_amount;
+        assert!(quote_amount < u64::from(buy.input_amount));
+
+        // The signer cranks, paid on the crossed amounts and on the residuals
+        let cranker_fee = |input_amount: u64| input_amount * cranker_fee_bps as u64 / 10_000;
+        let user_base_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.base_mint);
+        let user_quote_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.quote_mint);
+        let base_before = token_balance(&ctx.svm, user_base_ata);
+        let quote_before = token_balance(&ctx.svm, user_quote_ata);
+
+        ctx.svm.expire_blockhash();
+        let tx = Transaction::new_signed_with_payer(
+            &[cross_instruction(&ctx, seller_escrow, buyer_escrow)],
+            Some(&ctx.signer.pubkey()),
+            &[&ctx.signer],
+            ctx.svm.latest_blockhash(),
+        );
+        let res = assert_tx!(ctx.svm.send_transaction(tx));
+        assert!(res.logs.iter().any(|log| log.contains(&format!(
+            "Crossing {} base for {} quote",
+            base_amount, quote_amount
+        ))));
+
+        let escrow_account = ctx.svm.get_account(&seller_escrow).unwrap();
+        let escrow = Escrow::read(&escrow_account.data).unwrap();
+        assert!(escrow.pass_quote_amount >= quote_amount);
+        assert!(escrow.pass_base_amount <= amount - base_amount);
+
+        // The buyer residual went through the pool
+        let escrow_account = ctx.svm.get_account(&buyer_escrow).unwrap();
+        let escrow = Escrow::read(&escrow_account.data).unwrap();
+        assert!(escrow.pass_base_amount > base_amount);
+        let PoolState::Futarchy {
+            pass: pass_after, ..
+        } = get_dao(&ctx.svm, ctx.dao).amm.state
+        else {
+            panic!("Markets are not live");
+        };
+        assert!(pass_after.quote_reserves > pass.quote_reserves);
+        assert!(pass_after.quote_reserves - pass.quote_reserves <= buy_residual);
+
+        assert_eq!(
+            token_balance(&ctx.svm, user_quote_ata) - quote_before,
+            cranker_fee(quote_amount) + cranker_fee(buy_residual)
+        );
+        assert_eq!(
+            token_balance(&ctx.svm, user_base_ata) - base_before,
+            cranker_fee(base_amount) + cranker_fee(sell_residual)
+        );
+    }
+}
+
+#[test]
+fn initialize_validation_test() {
+    let mut ctx = TestContext::new(1_000_000_000);
+
+    let valid_args = default_args();
+    let market_making_args = InitializeEscrowArgs {
+        bullish_threshold_bps: 0.into(),
+        strategy: EscrowStrategy::MarketMaking as u8,
+        market_making_band_bps: 200.into(),
+        ..valid_args
+    };
+
+    let rejections = [
+        (
+            InitializeEscrowArgs {
+                cranker_fee_bps: (MAX_CRANKER_FEE_BPS + 1).into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidCrankerFee,
+        ),
+        (
+            InitializeEscrowArgs {
+                max_slippage_bps: (BPS_DENOMINATOR as u16 + 1).into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidMaxSlippage,
+        ),
+        (
+            InitializeEscrowArgs {
+                max_trade_per_window: 1000.into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidTradeWindow,
+        ),
+        (
+            InitializeEscrowArgs {
+                spot_target_base_bps: (BPS_DENOMINATOR as u16 + 1).into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidSpotTarget,
+        ),
+        (
+            InitializeEscrowArgs {
+                strategy: 3,
+                ..valid_args
+            },
+            LobbyistError::InvalidStrategy,
+        ),
+        (
+            InitializeEscrowArgs {
+                price_source: 4,
+                ..valid_args
+            },
+            LobbyistError::InvalidPriceSource,
+        ),
+        (
+            InitializeEscrowArgs {
+                preferences_delay_seconds: (MIN_PREFERENCES_DELAY_SECONDS - 1).into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidPreferencesDelay,
+        ),
+        (
+            InitializeEscrowArgs {
+                bullish_threshold_bps: (-1).into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidBullishThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                bullish_threshold_bps: (MAX_THRESHOLD_BPS + 1).into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidBullishThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                bearish_threshold_bps: (-1).into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidBearishThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                bearish_threshold_bps: (MAX_THRESHOLD_BPS + 1).into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidBearishThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                bullish_threshold_bps: 0.into(),
+                ..valid_args
+            },
+            LobbyistError::MissingStrategyThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                strategy: EscrowStrategy::Bearish as u8,
+                ..valid_args
+            },
+            LobbyistError::MissingStrategyThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                bearish_threshold_bps: 11000.into(),
+                ..valid_args
+            },
+            LobbyistError::UnusedStrategyThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                bearish_threshold_bps: 11000.into(),
+                strategy: EscrowStrategy::Bearish as u8,
+                ..valid_args
+            },
+            LobbyistError::UnusedStrategyThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                market_making_band_bps: 200.into(),
+                ..valid_args
+            },
+            LobbyistError::InvalidMarketMakingBand,
+        ),
+        (
+            InitializeEscrowArgs {
+                bearish_threshold_bps: 11000.into(),
+                ..market_making_args
+            },
+            LobbyistError::UnusedStrategyThreshold,
+        ),
+        (
+            InitializeEscrowArgs {
+                market_making_band_bps: 0.into(),
+                ..market_making_args
+            },
+            LobbyistError::InvalidMarketMakingBand,
+        ),
+        (
+            InitializeEscrowArgs {
+                market_making_band_bps: 10000.into(),
+                ..market_making_args
+            },
+            LobbyistError::InvalidMarketMakingBand,
+        ),
+    ];
+
+    for (args, error) in rejections {
+        assert_error(
+            &mut ctx,
+            initialize_escrow_instruction(&ctx, ctx.signer.pubkey(), args),
+            error,
+        );
+    }
+
+    let escrow_pda = setup_escrow(&mut ctx, valid_args, 0, 0);
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.bullish_threshold_bps, 12000);
+    assert_eq!(escrow.bearish_threshold_bps, 0);
+    assert_eq!(escrow.strategy, EscrowStrategy::Bullish as u8);
+}
+
+#[test]
+fn status_test() {
+    let mut ctx = TestContext::new(1_000_000_000);
+
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), 0, 0);
+
+    // Escrows start paused
+    let quote_ix = QuoteTradeInstruction {
+        ctx: trade_context(&ctx, escrow_pda, ctx.signer.pubkey()),
+    }
+    .into_instruction();
+    let tx = Transaction::new_signed_with_payer(
+        &[quote_ix],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    let err = ctx.svm.simulate_transaction(tx).unwrap_err();
+    assert!(format!("{:?}", err.err).contains(&format!(
+        "Custom({})",
+        LobbyistError::EscrowNotActive as u32
+    )));
+
+    assert_error(
+        &mut ctx,
+        set_status_instruction(ctx.signer.pubkey(), escrow_pda, EscrowStatus::Paused),
+        LobbyistError::EscrowAlreadyPaused,
+    );
+    assert_error(
+        &mut ctx,
+        set_status_instruction(ctx.signer.pubkey(), escrow_pda, EscrowStatus::Done),
+        LobbyistError::StatusRequiresSettlement,
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[set_status_instruction(
+            ctx.signer.pubkey(),
+            escrow_pda,
+            EscrowStatus::Active,
+        )],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    assert_error(
+        &mut ctx,
+        set_status_instruction(ctx.signer.pubkey(), escrow_pda, EscrowStatus::Active),
+        LobbyistError::EscrowAlreadyActive,
+    );
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.status, EscrowStatus::Active as u8);
+
+    // Tokens only leave a paused escrow
+    let ixs = conditional_mints(&ctx).map(|mint| {
+        create_associated_token_account_idempotent(
+            &ctx.signer.pubkey(),
+            &ctx.signer.pubkey(),
+            &mint,
+            &spl_token::ID,
+        )
+    });
+    let tx = Transaction::new_signed_with_payer(
+        &ixs,
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+    let withdraw_conditional_ix = withdraw_conditional_instruction(
+        &ctx,
+        escrow_pda,
+        conditional_mints(&ctx),
+        WithdrawConditionalArgs {
+            pass_base_amount: 0.into(),
+            pass_quote_amount: 0.into(),
+            fail_base_amount: 0.into(),
+            fail_quote_amount: 0.into(),
+        },
+    );
+    for ix in [
+        withdraw_instruction(&ctx, escrow_pda, 0, 0),
+        withdraw_conditional_ix,
+        close_instruction(&ctx, escrow_pda),
+    ] {
+        assert_error(&mut ctx, ix, LobbyistError::EscrowNotPaused);
+    }
+
+    // Nor once it is settled
+    finalize(&mut ctx, true);
+    let tx = Transaction::new_signed_with_payer(
+        &[settle_instruction(&ctx, escrow_pda, ctx.signer.pubkey())],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+    for ix in [
+        deposit_instruction(&ctx, ctx.signer.pubkey(), 0, 1000),
+        withdraw_instruction(&ctx, escrow_pda, 0, 0),
+    ] {
+        assert_error(&mut ctx, ix, LobbyistError::EscrowAlreadySettled);
+    }
+}
+
+#[test]
+fn update_preferences_test() {
+    let mut ctx = TestContext::new(1_000_000_000);
+
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), 0, 0);
+    let depositor = ctx.signer.pubkey();
+    let update_ix = move |args| {
+        UpdatePreferencesInstruction {
+            ctx: UpdatePreferencesContext {
+                depositor,
+                escrow: escrow_pda,
+                args,
+            },
+        }
+        .into_instruction()
+    };
+    let bearish_args = UpdatePreferencesArgs {
+        bullish_threshold_bps: 0.into(),
+        bearish_threshold_bps: 11000.into(),
+        strategy: EscrowStrategy::Bearish as u8,
+        market_making_band_bps: 0.into(),
+    };
+    let market_making_args = UpdatePreferencesArgs {
+        bullish_threshold_bps: 0.into(),
+        bearish_threshold_bps: 0.into(),
+        strategy: EscrowStrategy::MarketMaking as u8,
+        market_making_band_bps: 200.into(),
+    };
+
+    // Updates go through the same validation as the initialization
+    assert_error(
+        &mut ctx,
+        update_ix(UpdatePreferencesArgs {
+            bearish_threshold_bps: 0.into(),
+            ..bearish_args
+        }),
+        LobbyistError::MissingStrategyThreshold,
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[update_ix(bearish_args)],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.strategy, EscrowStrategy::Bullish as u8);
+    assert_eq!(escrow.bullish_threshold_bps, 12000);
+    assert_eq!(escrow.pending_strategy, EscrowStrategy::Bearish as u8);
+    assert_eq!(
+        escrow.pending_preferences_timestamp,
+        MIN_PREFERENCES_DELAY_SECONDS as i64
+    );
+
+    // The next update applies the matured one before scheduling itself
+    ctx.svm.set_sysvar::<Clock>(&Clock {
+        unix_timestamp: MIN_PREFERENCES_DELAY_SECONDS as i64,
+        epoch_start_timestamp: 100,
+        epoch: 100,
+        leader_schedule_epoch: 100,
+        slot: 100,
+    });
+    let tx = Transaction::new_signed_with_payer(
+        &[update_ix(market_making_args)],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.strategy, EscrowStrategy::Bearish as u8);
+    assert_eq!(escrow.bullish_threshold_bps, 0);
+    assert_eq!(escrow.bearish_threshold_bps, 11000);
+    assert_eq!(escrow.pending_strategy, EscrowStrategy::MarketMaking as u8);
+    assert_eq!(escrow.pending_market_making_band_bps, 200);
+    assert_eq!(
+        escrow.pending_preferences_timestamp,
+        2 * MIN_PREFERENCES_DELAY_SECONDS as i64
+    );
+}
+
+#[test]
+fn close_escrow_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), initial_supply / 2, 0);
+
+    let close_ix = close_instruction(&ctx, escrow_pda);
+    assert_error(&mut ctx, close_ix.clone(), LobbyistError::EscrowNotEmpty);
+
+    let withdraw_ix = withdraw_instruction(&ctx, escrow_pda, initial_supply / 2, 0);
+    let accounts = escrow_accounts(&ctx, escrow_pda);
+    let rent: u64 = accounts
+        .iter()
+        .map(|account| ctx.svm.get_account(account).unwrap().lamports)
+        .sum();
+    let balance = ctx.svm.get_balance(&ctx.signer.pubkey()).unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[withdraw_ix, close_ix],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    for account in accounts {
+        assert!(ctx
+            .svm
+            .get_account(&account)
+            .is_none_or(|account| account.lamports == 0));
+    }
+    assert_eq!(
+        ctx.svm.get_balance(&ctx.signer.pubkey()).unwrap(),
+        balance + rent - SIGNATURE_FEE
+    );
+}
+
+#[test]
+fn close_settled_escrow_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), 0, initial_supply / 100);
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+    crank(&mut ctx, escrow_pda);
+
+    finalize(&mut ctx, true);
+    let tx = Transaction::new_signed_with_payer(
+        &[settle_instruction(&ctx, escrow_pda, ctx.signer.pubkey())],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    // Dust left behind in settled escrow token accounts does not block the close
+    let dust = 7;
+    for mint in [ctx.quote_mint, ctx.fail_quote_mint] {
+        transfer_tokens(&mut ctx.svm, &ctx.signer, escrow_pda, mint, dust);
+    }
+    let user_quote_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.quote_mint);
+    let quote_before = token_balance(&ctx.svm, user_quote_ata);
+
+    let accounts = escrow_accounts(&ctx, escrow_pda);
+    let rent: u64 = accounts
+        .iter()
+        .map(|account| ctx.svm.get_account(account).unwrap().lamports)
+        .sum();
+    let balance = ctx.svm.get_balance(&ctx.signer.pubkey()).unwrap();
+
+    ctx.svm.expire_blockhash();
+    let tx = Transaction::new_signed_with_payer(
+        &[close_instruction(&ctx, escrow_pda)],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    assert_eq!(token_balance(&ctx.svm, user_quote_ata), quote_before + dust);
+    for account in accounts {
+        assert!(ctx
+            .svm
+            .get_account(&account)
+            .is_none_or(|account| account.lamports == 0));
+    }
+    assert_eq!(
+        ctx.svm.get_balance(&ctx.signer.pubkey()).unwrap(),
+        balance + rent - SIGNATURE_FEE
+    );
+}
+
+#[test]
+fn sync_escrow_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), initial_supply / 2, 0);
+    let escrow_base_ata = get_associated_token_address(&escrow_pda, &ctx.base_mint);
+    let second_deposit_ix = deposit_instruction(&ctx, ctx.signer.pubkey(), initial_supply / 4, 0);
+
+    // The escrow lost tokens it still tracks
+    set_token_balance(&mut ctx.svm, escrow_base_ata, initial_supply / 8);
+    assert_error(
+        &mut ctx,
+        second_deposit_ix.clone(),
+        LobbyistError::TrackedAmountExceedsBalance,
+    );
+
+    // Anyone can reconcile the bookkeeping
+    let cranker = Keypair::new();
+    ctx.svm
+        .airdrop(&cranker.pubkey(), LAMPORTS_PER_SOL)
+        .unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[sync_instruction(&ctx, escrow_pda, cranker.pubkey())],
+        Some(&cranker.pubkey()),
+        &[&cranker],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.base_amount, initial_supply / 8);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[second_deposit_ix],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.base_amount, initial_supply / 8 + initial_supply / 4);
+
+    // Tokens sent to the escrow are only tracked once its depositor syncs
+    transfer_tokens(
+        &mut ctx.svm,
+        &ctx.signer,
+        escrow_pda,
+        ctx.quote_mint,
+        initial_supply / 100,
+    );
+    for (authority, quote_amount) in [
+        (&cranker, 0),
+        (&ctx.signer.insecure_clone(), initial_supply / 100),
+    ] {
+        ctx.svm.expire_blockhash();
+        let tx = Transaction::new_signed_with_payer(
+            &[sync_instruction(&ctx, escrow_pda, authority.pubkey())],
+            Some(&authority.pubkey()),
+            &[authority],
+            ctx.svm.latest_blockhash(),
+        );
+        assert_tx!(ctx.svm.send_transaction(tx));
+
+        let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+        let escrow = Escrow::read(&escrow_account.data).unwrap();
+        assert_eq!(escrow.quote_amount, quote_amount);
+    }
+}
+
+#[test]
+fn close_after_donation_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), 0, 0);
+    let user_quote_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.quote_mint);
+    let quote_before = token_balance(&ctx.svm, user_quote_ata);
+
+    // A third party sends tokens to the empty paused escrow and syncs it
+    let griefer = create_depositor(&mut ctx, 0, initial_supply / 100);
+    transfer_tokens(
+        &mut ctx.svm,
+        &griefer,
+        escrow_pda,
+        ctx.quote_mint,
+        initial_supply / 100,
+    );
+    let tx = Transaction::new_signed_with_payer(
+        &[sync_instruction(&ctx, escrow_pda, griefer.pubkey())],
+        Some(&griefer.pubkey()),
+        &[&griefer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    let tx = Transaction::new_signed_with_payer(
+        &[close_instruction(&ctx, escrow_pda)],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    assert!(ctx.svm.get_account(&escrow_pda).is_none());
+    assert_eq!(
+        token_balance(&ctx.svm, user_quote_ata),
+        quote_before + initial_supply / 100
+    );
+}
+
+#[test]
+fn price_source_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    // Both escrows buy the pass market up to 120% of spot, judging it on different prices
+    let observation_escrow = setup_escrow(
+        &mut ctx,
+        InitializeEscrowArgs {
+            price_source: EscrowPriceSource::LastObservation as u8,
+            ..default_args()
+        },
+        0,
+        initial_supply / 10,
+    );
+    let depositor = create_depositor(&mut ctx, 0, initial_supply / 10);
+    let reserves_escrow = setup_escrow_for(
+        &mut ctx,
+        &depositor,
+        InitializeEscrowArgs {
+            price_source: EscrowPriceSource::Reserves as u8,
+            ..default_args()
+        },
+        0,
+        initial_supply / 10,
+    );
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+    activate_escrow(&mut ctx, &depositor);
+
+    // A large buy lifts the pass reserves, the oracle observation follows 2% per update
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 25);
+    for second in 1..=12 {
+        set_timestamp(&mut ctx, 80000 + second);
+        swap_market(&mut ctx, Market::Pass, SwapType::Buy, 1000);
+    }
+    // Then the reserves fall back under the threshold, the observation staying above it
+    swap_market(&mut ctx, Market::Pass, SwapType::Sell, initial_supply / 40);
+
+    let PoolState::Futarchy { pass, .. } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are not live");
+    };
+    assert!(pass.oracle.last_observation >= PRICE_SCALE * 12 / 10);
+    let pass_price = reserves_price(pass.base_reserves, pass.quote_reserves).unwrap();
+    assert!(pass_price < PRICE_SCALE * 12 / 10);
+
+    let quote = quote_trade(&mut ctx, observation_escrow);
+    assert!(!bool::from(quote.trade));
+
+    let quote = quote_trade(&mut ctx, reserves_escrow);
+    assert!(bool::from(quote.trade));
+    assert_eq!(quote.market, Market::Pass as u8);
+    assert_eq!(quote.swap_type, SwapType::Buy as u8);
+}
+
+#[test]
+fn oracle_age_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), 0, initial_supply / 100);
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+    let depositor = create_depositor(&mut ctx, 0, initial_supply / 100);
+    let patient_escrow = setup_escrow_for(
+        &mut ctx,
+        &depositor,
+        InitializeEscrowArgs {
+            max_oracle_age_seconds: 3600.into(),
+            ..default_args()
+        },
+        0,
+        initial_supply / 100,
+    );
+    activate_escrow(&mut ctx, &depositor);
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+
+    // Without a swap right before the crank the default age is exceeded
+    let crank_timestamp = 80000 + MAXIMUM_AGE as i64 + 1;
+    set_timestamp(&mut ctx, crank_timestamp);
+    assert_error(
+        &mut ctx,
+        trade_instruction(&ctx, escrow_pda),
+        LobbyistError::StaleOracle,
+    );
+
+    // A configured age lets the crank run on the same oracle
+    crank(&mut ctx, patient_escrow);
+    let escrow_account = ctx.svm.get_account(&patient_escrow).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.pass_base_amount > 0);
+
+    // Up to its own limit, counted from its own swap
+    set_timestamp(&mut ctx, crank_timestamp + 3601);
+    assert_error(
+        &mut ctx,
+        trade_instruction(&ctx, patient_escrow),
+        LobbyistError::StaleOracle,
+    );
+
+    // A fresh swap brings the default escrow back
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, 1000);
+    crank(&mut ctx, escrow_pda);
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.pass_base_amount > 0);
+}
+
+#[test]
+fn trade_window_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let max_trade_per_window = initial_supply / 1000;
+    let escrow_pda = setup_escrow(
+        &mut ctx,
+        InitializeEscrowArgs {
+            max_trade_per_window: max_trade_per_window.into(),
+            trade_window_seconds: 3600.into(),
+            ..default_args()
+        },
+        0,
+        initial_supply / 10,
+    );
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+    crank(&mut ctx, escrow_pda);
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.window_start_timestamp, 80000);
+    assert_eq!(escrow.window_traded_amount, max_trade_per_window);
+
+    // The pass market is still below target, but the window is spent
+    assert_error(
+        &mut ctx,
+        trade_instruction(&ctx, escrow_pda),
+        LobbyistError::TradeWindowExhausted,
+    );
+
+    // Trading resumes in the next window
+    set_timestamp(&mut ctx, 80000 + 3600);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+    crank(&mut ctx, escrow_pda);
+
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.window_start_timestamp, 80000 + 3600);
+    assert_eq!(escrow.window_traded_amount, max_trade_per_window);
+}
+
+#[test]
+fn trade_batch_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let signer_escrow = setup_escrow(&mut ctx, default_args(), 0, initial_supply / 100);
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    let active = create_depositor(&mut ctx, 0, initial_supply / 100);
+    let active_escrow =
+        setup_escrow_for(&mut ctx, &active, default_args(), 0, initial_supply / 100);
+    activate_escrow(&mut ctx, &active);
+
+    // Left paused
+    let paused = create_depositor(&mut ctx, 0, initial_supply / 100);
+    let paused_escrow =
+        setup_escrow_for(&mut ctx, &paused, default_args(), 0, initial_supply / 100);
+
+    // Listed with a token account of the escrow that is not its associated one
+    let spoofed = create_depositor(&mut ctx, 0, initial_supply / 100);
+    let spoofed_escrow =
+        setup_escrow_for(&mut ctx, &spoofed, default_args(), 0, initial_supply / 100);
+    activate_escrow(&mut ctx, &spoofed);
+    let spoofed_quote_account =
+        create_token_account(&mut ctx.svm, &ctx.signer, ctx.quote_mint, spoofed_escrow);
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+
+    let mut batch_ix = TradeBatchInstruction {
+        ctx: trade_context(&ctx, signer_escrow, ctx.signer.pubkey()),
+    }
+    .into_instruction();
+    for escrow_pda in [active_escrow, paused_escrow, spoofed_escrow] {
+        batch_ix
+            .accounts
+            .extend(batch_escrow_accounts(&ctx, escrow_pda));
+    }
+    batch_ix.accounts[batch_ix.accounts.len() - BATCH_ESCROW_ACCOUNTS + 2] =
+        AccountMeta::new(spoofed_quote_account, false);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[compute_unit_limit_instruction(1_400_000), batch_ix],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    let res = assert_tx!(ctx.svm.send_transaction(tx));
+    assert_eq!(
+        res.logs
+            .iter()
+            .filter(|log| log.contains("Skipping escrow"))
+            .count(),
+        2
+    );
+
+    for (escrow_pda, traded) in [
+        (signer_escrow, true),
+        (active_escrow, true),
+        (paused_escrow, false),
+        (spoofed_escrow, false),
+    ] {
+        let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+        let escrow = Escrow::read(&escrow_account.data).unwrap();
+        assert_eq!(escrow.pass_base_amount > 0, traded);
+        assert_eq!(escrow.quote_amount < initial_supply / 100, traded);
+    }
+
+    // Stops before a crank that might not fit in the remaining compute budget
+    let mut batch_ix = TradeBatchInstruction {
+        ctx: trade_context(&ctx, signer_escrow, ctx.signer.pubkey()),
+    }
+    .into_instruction();
+    let tx = Transaction::new_signed_with_payer(
+        &[compute_unit_limit_instruction(1_400_000), batch_ix.clone()],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    let consumed = assert_tx!(ctx.svm.simulate_transaction(tx))
+        .meta
+        .compute_units_consumed;
+
+    batch_ix
+        .accounts
+        .extend(batch_escrow_accounts(&ctx, active_escrow));
+    let before = ctx.svm.get_account(&active_escrow).unwrap().data;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            compute_unit_limit_instruction((consumed + CRANK_COMPUTE_UNITS / 2) as u32),
+            batch_ix,
+        ],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    let res = assert_tx!(ctx.svm.send_transaction(tx));
+    assert!(res.logs.iter().any(|log| log.contains("Stopping batch")));
+    assert_eq!(ctx.svm.get_account(&active_escrow).unwrap().data, before);
+}
+
+#[test]
+fn trade_batch_reload_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    // Funded enough to bring the pass price to the target on its own
+    let signer_escrow = setup_escrow(&mut ctx, default_args(), 0, initial_supply / 10);
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    let other = create_depositor(&mut ctx, 0, initial_supply / 10);
+    let other_escrow = setup_escrow_for(&mut ctx, &other, default_args(), 0, initial_supply / 10);
+    activate_escrow(&mut ctx, &other);
+
+    set_timestamp(&mut ctx, 80000);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+
+    let mut batch_ix = TradeBatchInstruction {
+        ctx: trade_context(&ctx, signer_escrow, ctx.signer.pubkey()),
+    }
+    .into_instruction();
+    batch_ix
+        .accounts
+        .extend(batch_escrow_accounts(&ctx, other_escrow));
+    let tx = Transaction::new_signed_with_payer(
+        &[compute_unit_limit_instruction(1_400_000), batch_ix],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    // The second escrow sees the pools the first one moved and has nothing left to buy
+    let dao = get_dao(&ctx.svm, ctx.dao);
+    let PoolState::Futarchy { spot, pass, .. } = dao.amm.state else {
+        panic!("Markets are not live");
+    };
+    let target_price = reserves_price(spot.base_reserves, spot.quote_reserves).unwrap() * 12 / 10;
+    let pass_price = reserves_price(pass.base_reserves, pass.quote_reserves).unwrap();
+    assert!(pass_price <= target_price);
+    assert!(pass_price >= target_price * 99 / 100);
+
+    let escrow_account = ctx.svm.get_account(&signer_escrow).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.pass_base_amount > 0);
+
+    let escrow_account = ctx.svm.get_account(&other_escrow).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!(escrow.pass_base_amount, 0);
+    assert_eq!(escrow.quote_amount, initial_supply / 10);
+}
+
+#[test]
+fn proposal_lifecycle_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new(initial_supply);
+
+    let escrow_pda = setup_escrow(&mut ctx, default_args(), 0, initial_supply / 100);
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+    let depositor = create_depositor(&mut ctx, 0, initial_supply / 100);
+    let cautious_escrow = setup_escrow_for(
+        &mut ctx,
+        &depositor,
+        InitializeEscrowArgs {
+            trade_cutoff_seconds: 3600.into(),
+            ..default_args()
+        },
+        0,
+        initial_supply / 100,
+    );
+    activate_escrow(&mut ctx, &depositor);
+
+    // Markets of another proposal do not trade escrows of a proposal that is not live
+    set_proposal_state(
+        &mut ctx.svm,
+        ctx.proposal,
+        ProposalState::Draft { amount_staked: 0 },
+    );
+    assert_error(
+        &mut ctx,
+        trade_instruction(&ctx, escrow_pda),
+        LobbyistError::ProposalNotPending,
+    );
+    set_proposal_state(&mut ctx.svm, ctx.proposal, ProposalState::Pending);
+
+    // Within its cutoff of the end only the escrow without one trades
+    let proposal = get_proposal(&ctx.svm, ctx.proposal);
+    let end_timestamp = trading_end_timestamp(&proposal);
+    set_timestamp(&mut ctx, end_timestamp - 1800);
+    swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+    assert_error(
+        &mut ctx,
+        trade_instruction(&ctx, cautious_escrow),
+        LobbyistError::ProposalTradingEnded,
+    );
+    crank(&mut ctx, escrow_pda);
+    let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.pass_base_amount > 0);
+
+    set_timestamp(&mut ctx, end_timestamp);
+    assert_error(
+        &mut ctx,
+        trade_instruction(&ctx, escrow_pda),
+        LobbyistError::ProposalTradingEnded,
+    );
+    assert_error(
+        &mut ctx,
+        deposit_instruction(&ctx, ctx.signer.pubkey(), 0, 1000),
+        LobbyistError::ProposalTradingEnded,
+    );
+
+    finalize(&mut ctx, true);
+    assert_error(
+        &mut ctx,
+        deposit_instruction(&ctx, ctx.signer.pubkey(), 0, 1000),
+        LobbyistError::ProposalFinalized,
+    );
+}
+
+#[test]
+fn settle_escrow_test() {
+    for passed in [true, false] {
+        let initial_supply = 1_000_000_000;
+        let mut ctx = TestContext::new(initial_supply);
+
+        let escrow_pda = setup_escrow(&mut ctx, default_args(), 0, initial_supply / 100);
+        let signer = ctx.signer.insecure_clone();
+        activate_escrow(&mut ctx, &signer);
+
+        set_timestamp(&mut ctx, 80000);
+        swap_market(&mut ctx, Market::Pass, SwapType::Buy, initial_supply / 1000);
+        crank(&mut ctx, escrow_pda);
+
+        // Pending proposals are not settled yet
+        assert_error(
+            &mut ctx,
+            settle_instruction(&ctx, escrow_pda, ctx.signer.pubkey()),
+            LobbyistError::ProposalNotFinalized,
+        );
+
+        finalize(&mut ctx, passed);
+        let state = get_proposal(&ctx.svm, ctx.proposal).state;
+        assert!(matches!(
+            (passed, state),
+            (true, ProposalState::Passed) | (false, ProposalState::Failed)
+        ));
+
+        let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+        let escrow = Escrow::read(&escrow_account.data).unwrap();
+        let user_base_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.base_mint);
+        let user_quote_ata = get_associated_token_address(&ctx.signer.pubkey(), &ctx.quote_mint);
+        let base_before = token_balance(&ctx.svm, user_base_ata);
+        let quote_before = token_balance(&ctx.svm, user_quote_ata);
+
+        let settle_ix = settle_instruction(&ctx, escrow_pda, ctx.signer.pubkey());
+        let tx = Transaction::new_signed_with_payer(
+            &[settle_ix.clone()],
+            Some(&ctx.signer.pubkey()),
+            &[&ctx.signer],
+            ctx.svm.latest_blockhash(),
+        );
+        assert_tx!(ctx.svm.send_transaction(tx));
+
+        // Only the winning conditional tokens redeem for the underlying
+        let (base_redeemed, quote_redeemed) = if passed {
+            (escrow.pass_base_amount, escrow.pass_quote_amount)
+        } else {
+            (escrow.fail_base_amount, escrow.fail_quote_amount)
+        };
+        assert_eq!(
+            token_balance(&ctx.svm, user_base_ata),
+            base_before + escrow.base_amount + base_redeemed
+        );
+        assert_eq!(
+            token_balance(&ctx.svm, user_quote_ata),
+            quote_before + escrow.quote_amount + quote_redeemed
+        );
+
+        let escrow_account = ctx.svm.get_account(&escrow_pda).unwrap();
+        let escrow = Escrow::read(&escrow_account.data).unwrap();
+        assert_eq!(
+            [
+                escrow.base_amount,
+                escrow.quote_amount,
+                escrow.pass_base_amount,
+                escrow.pass_quote_amount,
+                escrow.fail_base_amount,
+                escrow.fail_quote_amount,
+            ],
+            [0; 6]
+        );
+        assert_eq!(escrow.status, EscrowStatus::Done as u8);
+
+        assert_error(&mut ctx, settle_ix, LobbyistError::EscrowAlreadySettled);
+    }
+}
+
+#[test]
+fn spot_rebalance_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new_draft(initial_supply);
+
+    let args = |spot_target_base_bps: u16| InitializeEscrowArgs {
+        spot_target_base_bps: spot_target_base_bps.into(),
+        price_source: EscrowPriceSource::Reserves as u8,
+        ..default_args()
+    };
+    let amount = initial_supply / 1000;
+    let buyer = create_depositor(&mut ctx, 0, amount);
+    let buyer_escrow = setup_escrow_for(&mut ctx, &buyer, args(5000), 0, amount);
+    let seller = create_depositor(&mut ctx, amount, 0);
+    let seller_escrow = setup_escrow_for(&mut ctx, &seller, args(5000), amount, 0);
+    let disabled_escrow = setup_escrow(&mut ctx, args(0), amount, amount);
+
+    let signer = ctx.signer.insecure_clone();
+    for depositor in [&buyer, &seller, &signer] {
+        activate_escrow(&mut ctx, depositor);
+    }
+    // Before the proposal launches
+    assert!(matches!(
+        get_proposal(&ctx.svm, ctx.proposal).state,
+        ProposalState::Draft { .. }
+    ));
+    assert!(matches!(
+        get_dao(&ctx.svm, ctx.dao).amm.state,
+        PoolState::Spot { .. }
+    ));
+
+    // A spot swap refreshes the oracle
+    let now = ctx.svm.get_sysvar::<Clock>().unix_timestamp;
+    set_timestamp(&mut ctx, now + 1);
+    swap_spot(&mut ctx, SwapType::Buy, 1000);
+    let PoolState::Spot { spot } = get_dao(&ctx.svm, ctx.dao).amm.state else {
+        panic!("Markets are live");
+    };
+    let spot_price = reserves_price(spot.base_reserves, spot.quote_reserves).unwrap();
+    let base_share = |escrow: &Escrow| {
+        let base_value = escrow.base_amount as u128 * spot_price / PRICE_SCALE;
+        base_value * BPS_DENOMINATOR / (base_value + escrow.quote_amount as u128)
+    };
+
+    // Buys base toward half of its value
+    crank(&mut ctx, buyer_escrow);
+    let escrow_account = ctx.svm.get_account(&buyer_escrow).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.base_amount > 0);
+    assert!(escrow.quote_amount < amount);
+    assert!(base_share(escrow) <= 5000);
+    assert!(base_share(escrow) > 4900);
+
+    // Sells base toward half of its value
+    crank(&mut ctx, seller_escrow);
+    let escrow_account = ctx.svm.get_account(&seller_escrow).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert!(escrow.base_amount < amount);
+    assert!(escrow.quote_amount > 0);
+    assert!(base_share(escrow) >= 5000);
+    assert!(base_share(escrow) < 5100);
+
+    // No target, no trade
+    let quote = quote_trade(&mut ctx, disabled_escrow);
+    assert!(!bool::from(quote.trade));
+    crank(&mut ctx, disabled_escrow);
+    let escrow_account = ctx.svm.get_account(&disabled_escrow).unwrap();
+    let escrow = Escrow::read(&escrow_account.data).unwrap();
+    assert_eq!((escrow.base_amount, escrow.quote_amount), (amount, amount));
+
+    // Once the proposal is finalized, escrows are only settled
+    ctx.launch();
+    finalize(&mut ctx, false);
+    let now = ctx.svm.get_sysvar::<Clock>().unix_timestamp;
+    set_timestamp(&mut ctx, now + 1);
+    swap_spot(&mut ctx, SwapType::Sell, 1000);
+    assert_error(
+        &mut ctx,
+        trade_instruction(&ctx, seller_escrow),
+        LobbyistError::ProposalFinalized,
+    );
+}
+
+#[test]
+fn slippage_test() {
+    let initial_supply = 1_000_000_000;
+    let mut ctx = TestContext::new_draft(initial_supply);
+
+    let escrow_pda = setup_escrow(
+        &mut ctx,
+        InitializeEscrowArgs {
+            spot_target_base_bps: 5000.into(),
+            ..default_args()
+        },
+        0,
+        initial_supply / 100,
+    );
+    let signer = ctx.signer.insecure_clone();
+    activate_escrow(&mut ctx, &signer);
+
+    // The spot reserves jump while the oracle moves 2% at most
+    let now = ctx.svm.get_sysvar::<Clock>().unix_timestamp;
+    set_timestamp(&mut ctx, now + 1);
+    swap_spot(&mut ctx, SwapType::Buy, initial_supply / 20);
+
+    // Buying at the pumped reserves falls short of the TWAP reference by more than 1%
+    assert_error(
+        &mut ctx,
+        trade_instruction(&ctx, escrow_pda),
+        LobbyistError::SlippageExceeded,
+    );
+}
+
+/// Lamports charged per transaction signature
+const SIGNATURE_FEE: u64 = 5000;
+
+/// Sends the instruction and checks it fails with the given error
+fn assert_error(ctx: &mut TestContext, ix: Instruction, error: LobbyistError) {
+    // Fresh blockhashes keep identical transactions from being rejected as duplicates
+    ctx.svm.expire_blockhash();
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    let err = ctx.svm.send_transaction(tx).unwrap_err();
+    assert!(
+        format!("{:?}", err.err).contains(&format!("Custom({})", error as u32)),
+        "{:?}",
+        err.err
+    );
+    ctx.svm.expire_blockhash();
+}
+
+/// Compute budget instruction setting the transaction compute unit limit
+fn compute_unit_limit_instruction(units: u32) -> Instruction {
+    let mut data = vec![2];
+    data.extend_from_slice(&units.to_le_bytes());
+    Instruction::new_with_bytes(solana_sdk_ids::compute_budget::ID, &data, vec![])
+}
+
+fn set_status_instruction(
+    depositor: Pubkey,
+    escrow_pda: Pubkey,
+    status: EscrowStatus,
+) -> Instruction {
+    SetStatusInstruction {
+        ctx: SetStatusContext {
+            depositor,
+            escrow: escrow_pda,
+            args: SetStatusArgs {
+                status: status as u8,
+            },
+        },
+    }
+    .into_instruction()
+}
+
+/// Preferences of a plain bullish escrow, tests override the fields they exercise
+fn default_args() -> InitializeEscrowArgs {
+    InitializeEscrowArgs {
+        bullish_threshold_bps: 12000.into(),
+        bearish_threshold_bps: 0.into(),
+        strategy: EscrowStrategy::Bullish as u8,
+        cranker_fee_bps: 0.into(),
+        max_slippage_bps: 100.into(),
+        max_trade_per_window: 0.into(),
+        trade_window_seconds: 0.into(),
+        max_oracle_age_seconds: 0.into(),
+        trade_cutoff_seconds: 0.into(),
+        spot_target_base_bps: 0.into(),
+        market_making_band_bps: 0.into(),
+        price_source: EscrowPriceSource::WindowedTwap as u8,
+        preferences_delay_seconds: MIN_PREFERENCES_DELAY_SECONDS.into(),
+    }
+}
+
+/// Initializes the signer's escrow and deposits into it, the escrow starts paused
+fn setup_escrow(
+    ctx: &mut TestContext,
+    args: InitializeEscrowArgs,
+    base_amount: u64,
+    quote_amount: u64,
+) -> Pubkey {
+    let depositor = ctx.signer.insecure_clone();
+    setup_escrow_for(ctx, &depositor, args, base_amount, quote_amount)
+}
+
+fn setup_escrow_for(
+    ctx: &mut TestContext,
+    depositor: &Keypair,
+    args: InitializeEscrowArgs,
+    base_amount: u64,
+    quote_amount: u64,
+) -> Pubkey {
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            initialize_escrow_instruction(ctx, depositor.pubkey(), args),
+            deposit_instruction(ctx, depositor.pubkey(), base_amount, quote_amount),
+        ],
+        Some(&depositor.pubkey()),
+        &[depositor],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+
+    escrow_address(ctx, depositor.pubkey())
+}
+
+/// Creates a funded depositor holding spot tokens taken from the signer
+fn create_depositor(ctx: &mut TestContext, base_amount: u64, quote_amount: u64) -> Keypair {
+    let depositor = Keypair::new();
+    ctx.svm
+        .airdrop(&depositor.pubkey(), LAMPORTS_PER_SOL)
+        .unwrap();
+    for (mint, amount) in [(ctx.base_mint, base_amount), (ctx.quote_mint, quote_amount)] {
+        transfer_tokens(&mut ctx.svm, &ctx.signer, depositor.pubkey(), mint, amount);
+    }
+
+    depositor
+}
+
+fn activate_escrow(ctx: &mut TestContext, depositor: &Keypair) {
+    let escrow_pda = escrow_address(ctx, depositor.pubkey());
+    let tx = Transaction::new_signed_with_payer(
+        &[set_status_instruction(
+            depositor.pubkey(),
+            escrow_pda,
+            EscrowStatus::Active,
+        )],
+        Some(&depositor.pubkey()),
+        &[depositor],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+}
+
+fn set_timestamp(ctx: &mut TestContext, unix_timestamp: i64) {
+    ctx.svm.set_sysvar::<Clock>(&Clock {
+        unix_timestamp,
+        epoch_start_timestamp: 100,
+        epoch: 100,
+        leader_schedule_epoch: 100,
+        slot: 100,
+    });
+}
+
+/// Swaps the signer conditional tokens in a decision market
+fn swap_market(ctx: &mut TestContext, market: Market, swap_type: SwapType, input_amount: u64) {
+    conditional_swap(
+        &mut ctx.svm,
+        &ctx.signer,
+        ctx.dao,
+        ctx.proposal,
+        ctx.question,
+        ctx.base_mint,
+        ctx.quote_mint,
+        ctx.base_vault_pda,
+        ctx.quote_vault_pda,
+        ctx.pass_base_mint,
+        ctx.pass_quote_mint,
+        ctx.fail_base_mint,
+        ctx.fail_quote_mint,
+        ConditionalSwapParams {
+            swap_type,
+            input_amount,
+            min_output_amount: 0,
+            market,
+        },
+    );
+}
+
+/// Finalizes the proposal past its duration, lifting the TWAP of the market that has to win
+fn finalize(ctx: &mut TestContext, passed: bool) {
+    let market = if passed { Market::Pass } else { Market::Fail };
+    let dao = get_dao(&ctx.svm, ctx.dao);
+    let PoolState::Futarchy { pass, fail, .. } = dao.amm.state else {
+        panic!("Markets are not live");
+    };
+    let pool = if passed { pass } else { fail };
+
+    // Oracle observations move at most 2% per update toward the pool price
+    let start = ctx.svm.get_sysvar::<Clock>().unix_timestamp;
+    swap_market(ctx, market.clone(), SwapType::Buy, pool.quote_reserves / 10);
+    for second in 1..=10 {
+        set_timestamp(ctx, start + second);
+        swap_market(ctx, market.clone(), SwapType::Buy, 1000);
+    }
+
+    // Both oracles have to be updated at the end of the proposal
+    set_timestamp(ctx, start + 10 + dao.seconds_per_proposal as i64);
+    swap_market(ctx, Market::Pass, SwapType::Buy, 1000);
+    swap_market(ctx, Market::Fail, SwapType::Buy, 1000);
+
+    finalize_proposal(
+        &mut ctx.svm,
+        &ctx.signer,
+        ctx.dao,
+        ctx.proposal,
+        ctx.question,
+        ctx.base_mint,
+        ctx.quote_mint,
+        ctx.base_vault_pda,
+        ctx.quote_vault_pda,
+        ctx.pass_base_mint,
+        ctx.pass_quote_mint,
+        ctx.fail_base_mint,
+        ctx.fail_quote_mint,
+    );
+}
+
+/// Swaps the signer spot tokens, while no proposal is live
+fn swap_spot(ctx: &mut TestContext, swap_type: SwapType, input_amount: u64) {
+    spot_swap(
+        &mut ctx.svm,
+        &ctx.signer,
+        ctx.dao,
+        ctx.base_mint,
+        ctx.quote_mint,
+        SpotSwapParams {
+            input_amount,
+            swap_type,
+            min_output_amount: 0,
+        },
+    );
+}
+
+/// Crank of the escrow paid and signed by the signer
+fn trade_instruction(ctx: &TestContext, escrow_pda: Pubkey) -> Instruction {
+    TradeInstruction {
+        ctx: trade_context(ctx, escrow_pda, ctx.signer.pubkey()),
+    }
+    .into_instruction()
+}
+
+fn crank(ctx: &mut TestContext, escrow_pda: Pubkey) {
+    ctx.svm.expire_blockhash();
+    let tx = Transaction::new_signed_with_payer(
+        &[trade_instruction(ctx, escrow_pda)],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    assert_tx!(ctx.svm.send_transaction(tx));
+}
+
+/// Simulates the next crank of the escrow
+fn quote_trade(ctx: &mut TestContext, escrow_pda: Pubkey) -> TradeQuote {
+    let quote_ix = QuoteTradeInstruction {
+        ctx: trade_context(ctx, escrow_pda, ctx.signer.pubkey()),
+    }
+    .into_instruction();
+
+    ctx.svm.expire_blockhash();
+    let tx = Transaction::new_signed_with_payer(
+        &[quote_ix],
+        Some(&ctx.signer.pubkey()),
+        &[&ctx.signer],
+        ctx.svm.latest_blockhash(),
+    );
+    let res = assert_tx!(ctx.svm.simulate_transaction(tx));
+
+    bytemuck::pod_read_unaligned(&res.meta.return_data.data)
+}
+
+fn escrow_address(ctx: &TestContext, depositor: Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &Escrow::derive(&depositor.to_bytes(), &ctx.proposal.to_bytes()),
+        &lobbyist::ID.into(),
+    )
+    .0
+}
+
+fn initialize_escrow_instruction(
+    ctx: &TestContext,
+    depositor: Pubkey,
+    args: InitializeEscrowArgs,
+) -> Instruction {
+    let escrow_pda = escrow_address(ctx, depositor);
+
+    InitializeEscrowInstruction {
+        ctx: InitializeEscrowContext {
+            depositor,
+            proposal: ctx.proposal,
+            escrow: escrow_pda,
+            dao: ctx.dao,
+            base_mint: ctx.base_mint,
+            quote_mint: ctx.quote_mint,
+            pass_base_mint: ctx.pass_base_mint,
+            pass_quote_mint: ctx.pass_quote_mint,
+            fail_base_mint: ctx.fail_base_mint,
+            fail_quote_mint: ctx.fail_quote_mint,
+            escrow_base_ata: get_associated_token_address(&escrow_pda, &ctx.base_mint),
+            escrow_quote_ata: get_associated_token_address(&escrow_pda, &ctx.quote_mint),
+            escrow_pass_base_ata: get_associated_token_address(&escrow_pda, &ctx.pass_base_mint),
+            escrow_pass_quote_ata: get_associated_token_address(&escrow_pda, &ctx.pass_quote_mint),
+            escrow_fail_base_ata: get_associated_token_address(&escrow_pda, &ctx.fail_base_mint),
+            escrow_fail_quote_ata: get_associated_token_address(&escrow_pda, &ctx.fail_quote_mint),
+            token_program: spl_token::ID.into(),
+            ata_token_program: spl_associated_token_account::ID.into(),
+            system_program: system_program::id(),
+            args,
+        },
+    }
+    .into_instruction()
+}
+
+fn deposit_instruction(
+    ctx: &TestContext,
+    depositor: Pubkey,
+    base_amount: u64,
+    quote_amount: u64,
+) -> Instruction {
+    let escrow_pda = escrow_address(ctx, depositor);
+
+    DepositInstruction {
+        ctx: DepositContext {
+            depositor,
+            proposal: ctx.proposal,
+            escrow: escrow_pda,
+            base_mint: ctx.base_mint,
+            quote_mint: ctx.quote_mint,
+            user_base_ata: get_associated_token_address(&depositor, &ctx.base_mint),
+            user_quote_ata: get_associated_token_address(&depositor, &ctx.quote_mint),
+            escrow_base_ata: get_associated_token_address(&escrow_pda, &ctx.base_mint),
+            escrow_quote_ata: get_associated_token_address(&escrow_pda, &ctx.quote_mint),
+            token_program: spl_token::ID.into(),
+            system_program: system_program::id(),
+            args: DepositArgs {
+                base_amount: base_amount.into(),
+                quote_amount: quote_amount.into(),
+            },
+        },
+    }
+    .into_instruction()
+}
+
+fn withdraw_instruction(
+    ctx: &TestContext,
+    escrow_pda: Pubkey,
+    base_amount: u64,
+    quote_amount: u64,
+) -> Instruction {
+    WithdrawInstruction {
+        ctx: WithdrawContext {
+            depositor: ctx.signer.pubkey(),
+            proposal: ctx.proposal,
+            escrow: escrow_pda,
+            base_mint: ctx.base_mint,
+            quote_mint: ctx.quote_mint,
+            user_base_ata: get_associated_token_address(&ctx.signer.pubkey(), &ctx.base_mint),
+            user_quote_ata: get_associated_token_address(&ctx.signer.pubkey(), &ctx.quote_mint),
+            escrow_base_ata: get_associated_token_address(&escrow_pda, &ctx.base_mint),
+            escrow_quote_ata: get_associated_token_address(&escrow_pda, &ctx.quote_mint),
+            token_program: spl_token::ID.into(),
+            ata_token_program: spl_associated_token_account::ID.into(),
+            system_program: system_program::id(),
+            args: WithdrawArgs {
+                base_amount: base_amount.into(),
+                quote_amount: quote_amount.into(),
+            },
+        },
+    }
+    .into_instruction()
+}
+
+fn close_instruction(ctx: &TestContext, escrow_pda: Pubkey) -> Instruction {
+    CloseEscrowInstruction {
+        ctx: CloseEscrowContext {
+            depositor: ctx.signer.pubkey(),
+            proposal: ctx.proposal,
+            escrow: escrow_pda,
+            base_mint: ctx.base_mint,
+            quote_mint: ctx.quote_mint,
+            pass_base_mint: ctx.pass_base_mint,
+            pass_quote_mint: ctx.pass_quote_mint,
+            fail_base_mint: ctx.fail_base_mint,
+            fail_quote_mint: ctx.fail_quote_mint,
+            user_base_ata: get_associated_token_address(&ctx.signer.pubkey(), &ctx.base_mint),
+            user_quote_ata: get_associated_token_address(&ctx.signer.pubkey(), &ctx.quote_mint),
+            escrow_base_ata: get_associated_token_address(&escrow_pda, &ctx.base_mint),
+            escrow_quote_ata: get_associated_token_address(&escrow_pda, &ctx.quote_mint),
+            escrow_pass_base_ata: get_associated_token_address(&escrow_pda, &ctx.pass_base_mint),
+            escrow_pass_quote_ata: get_associated_token_address(&escrow_pda, &ctx.pass_quote_mint),
+            escrow_fail_base_ata: get_associated_token_address(&escrow_pda, &ctx.fail_base_mint),
+            escrow_fail_quote_ata: get_associated_token_address(&escrow_pda, &ctx.fail_quote_mint),
+            token_program: spl_token::ID.into(),
+        },
+    }
+    .into_instruction()
+}
+
+/// Withdrawal of the signer's conditional tokens, listing the given `[pass base, pass quote,
+/// fail base, fail quote]` mints
+fn withdraw_conditional_instruction(
+    ctx: &TestContext,
+    escrow_pda: Pubkey,
+    mints: [Pubkey; 4],
+    args: WithdrawConditionalArgs,
+) -> Instruction {
+    let [pass_base_mint, pass_quote_mint, fail_base_mint, fail_quote_mint] = mints;
+    let depositor = ctx.signer.pubkey();
+
+    WithdrawConditionalInstruction {
+        ctx: WithdrawConditionalContext {
+            depositor,
+            proposal: ctx.proposal,
+            escrow: escrow_pda,
+            pass_base_mint,
+            pass_quote_mint,
+            fail_base_mint,
+            fail_quote_mint,
+            user_pass_base_ata: get_associated_token_address(&depositor, &pass_base_mint),
+            user_pass_quote_ata: get_associated_token_address(&depositor, &pass_quote_mint),
+            user_fail_base_ata: get_associated_token_address(&depositor, &fail_base_mint),
+            user_fail_quote_ata: get_associated_token_address(&depositor, &fail_quote_mint),
+            escrow_pass_base_ata: get_associated_token_address(&escrow_pda, &pass_base_mint),
+            escrow_pass_quote_ata: get_associated_token_address(&escrow_pda, &pass_quote_mint),
+            escrow_fail_base_ata: get_associated_token_address(&escrow_pda, &fail_base_mint),
+            escrow_fail_quote_ata: get_associated_token_address(&escrow_pda, &fail_quote_mint),
+            token_program: spl_token::ID.into(),
+            args,
+        },
+    }
+    .into_instruction()
+}
+
+fn conditional_mints(ctx: &TestContext) -> [Pubkey; 4] {
+    [
+        ctx.pass_base_mint,
+        ctx.pass_quote_mint,
+        ctx.fail_base_mint,
+        ctx.fail_quote_mint,
+    ]
+}
+
+fn sync_instruction(ctx: &TestContext, escrow_pda: Pubkey, authority: Pubkey) -> Instruction {
+    SyncEscrowInstruction {
+        ctx: SyncEscrowContext {
+            authority,
+            proposal: ctx.proposal,
+            escrow: escrow_pda,
+            base_mint: ctx.base_mint,
+            quote_mint: ctx.quote_mint,
+            pass_base_mint: ctx.pass_base_mint,
+            pass_quote_mint: ctx.pass_quote_mint,
+            fail_base_mint: ctx.fail_base_mint,
+            fail_quote_mint: ctx.fail_quote_mint,
+            escrow_base_ata: get_associated_token_address(&escrow_pda, &ctx.base_mint),
+            escrow_quote_ata: get_associated_token_address(&escrow_pda, &ctx.quote_mint),
+            escrow_pass_base_ata: get_associated_token_address(&escrow_pda, &ctx.pass_base_mint),
+            escrow_pass_quote_ata: get_associated_token_address(&escrow_pda, &ctx.pass_quote_mint),
+            escrow_fail_base_ata: get_associated_token_address(&escrow_pda, &ctx.fail_base_mint),
+            escrow_fail_quote_ata: get_associated_token_address(&escrow_pda, &ctx.fail_quote_mint),
+        },
+    }
+    .into_instruction()
+}
+
+/// Cross of the first escrow with the second, listed in the remaining accounts
+fn cross_instruction(
+    ctx: &TestContext,
+    first_escrow: Pubkey,
+    second_escrow: Pubkey,
+) -> Instruction {
+    let mut cross_ix = CrossEscrowsInstruction {
+        ctx: trade_context(ctx, first_escrow, ctx.signer.pubkey()),
+    }
+    .into_instruction();
+    cross_ix
+        .accounts
+        .extend(batch_escrow_accounts(ctx, second_escrow));
+
+    cross_ix
+}
+
+/// Permissionless settlement of the depositor's escrow, sent by the signer
+fn settle_instruction(ctx: &TestContext, escrow_pda: Pubkey, depositor: Pubkey) -> Instruction {
+    SettleEscrowInstruction {
+        ctx: SettleEscrowContext {
+            depositor,
+            proposal: ctx.proposal,
+            escrow: escrow_pda,
+            base_mint: ctx.base_mint,
+            quote_mint: ctx.quote_mint,
+            pass_base_mint: ctx.pass_base_mint,
+            pass_quote_mint: ctx.pass_quote_mint,
+            fail_base_mint: ctx.fail_base_mint,
+            fail_quote_mint: ctx.fail_quote_mint,
+            user_base_ata: get_associated_token_address(&depositor, &ctx.base_mint),
+            user_quote_ata: get_associated_token_address(&depositor, &ctx.quote_mint),
+            escrow_base_ata: get_associated_token_address(&escrow_pda, &ctx.base_mint),
+            escrow_quote_ata: get_associated_token_address(&escrow_pda, &ctx.quote_mint),
+            escrow_pass_base_ata: get_associated_token_address(&escrow_pda, &ctx.pass_base_mint),
+            escrow_pass_quote_ata: get_associated_token_address(&escrow_pda, &ctx.pass_quote_mint),
+            escrow_fail_base_ata: get_associated_token_address(&escrow_pda, &ctx.fail_base_mint),
+            escrow_fail_quote_ata: get_associated_token_address(&escrow_pda, &ctx.fail_quote_mint),
+            base_vault: ctx.base_vault_pda,
+            base_vault_underlying_token_account: get_associated_token_address(
+                &ctx.base_vault_pda,
+                &ctx.base_mint,
+            ),
+            quote_vault: ctx.quote_vault_pda,
+            quote_vault_underlying_token_account: get_associated_token_address(
+                &ctx.quote_vault_pda,
+                &ctx.quote_mint,
+            ),
+            question: ctx.question,
+            vault_event_authority: conditional_vault_event_authority_pda(),
+            conditional_vault_program: CONDITIONAL_VAULT_PROGRAM_ID,
+            token_program: spl_token::ID.into(),
+        },
+    }
+    .into_instruction()
+}
+
+/// The escrow and its token accounts
+fn escrow_accounts(ctx: &TestContext, escrow_pda: Pubkey) -> Vec<Pubkey> {
+    batch_escrow_accounts(ctx, escrow_pda)
+        .into_iter()
+        .map(|account| account.pubkey)
+        .collect()
+}
+
+/// Remaining accounts listing an escrow in a batch or a cross
+fn batch_escrow_accounts(ctx: &TestContext, escrow_pda: Pubkey) -> Vec<AccountMeta> {
+    let mints = [
+        ctx.base_mint,
+        ctx.quote_mint,
+        ctx.pass_base_mint,
+        ctx.pass_quote_mint,
+        ctx.fail_base_mint,
+        ctx.fail_quote_mint,
+    ];
+
+    std::iter::once(escrow_pda)
+        .chain(mints.map(|mint| get_associated_token_address(&escrow_pda, &mint)))
+        .map(|account| AccountMeta::new(account, false))
+        .collect()
+}
+
+fn trade_context(ctx: &TestContext, escrow_pda: Pubkey, cranker: Pubkey) -> TradeContext {
+    TradeContext {
+        cranker,
+        dao: ctx.dao,
+        proposal: ctx.proposal,
+        escrow: escrow_pda,
+        base_mint: ctx.base_mint,
+        quote_mint: ctx.quote_mint,
+        pass_base_mint: ctx.pass_base_mint,
+        pass_quote_mint: ctx.pass_quote_mint,
+        fail_base_mint: ctx.fail_base_mint,
+        fail_quote_mint: ctx.fail_quote_mint,
+        escrow_base_ata: get_associated_token_address(&escrow_pda, &ctx.base_mint),
+        escrow_quote_ata: get_associated_token_address(&escrow_pda, &ctx.quote_mint),
+        escrow_pass_base_ata: get_associated_token_address(&escrow_pda, &ctx.pass_base_mint),
+        escrow_pass_quote_ata: get_associated_token_address(&escrow_pda, &ctx.pass_quote_mint),
+        escrow_fail_base_ata: get_associated_token_address(&escrow_pda, &ctx.fail_base_mint),
+        escrow_fail_quote_ata: get_associated_token_address(&escrow_pda, &ctx.fail_quote_mint),
+        cranker_quote_ata: get_associated_token_address(&cranker, &ctx.quote_mint),
+        cranker_base_ata: get_associated_token_address(&cranker, &ctx.base_mint),
+        amm_base_vault: get_associated_token_address(&ctx.dao, &ctx.base_mint),
+        amm_quote_vault: get_associated_token_address(&ctx.dao, &ctx.quote_mint),
+        amm_pass_base_vault: get_associated_token_address(&ctx.dao, &ctx.pass_base_mint),
+        amm_pass_quote_vault: get_associated_token_address(&ctx.dao, &ctx.pass_quote_mint),
+        amm_fail_base_vault: get_associated_token_address(&ctx.dao, &ctx.fail_base_mint),
+        amm_fail_quote_vault: get_associated_token_address(&ctx.dao, &ctx.fail_quote_mint),
+        base_vault: ctx.base_vault_pda,
+        base_vault_underlying_token_account: get_associated_token_address(
+            &ctx.base_vault_pda,
+            &ctx.base_mint,
+        ),
+        quote_vault: ctx.quote_vault_pda,
+        quote_vault_underlying_token_account: get_associated_token_address(
+            &ctx.quote_vault_pda,
+            &ctx.quote_mint,
+        ),
+        question: ctx.question,
+        vault_event_authority: conditional_vault_event_authority_pda(),
+        conditional_vault_program: CONDITIONAL_VAULT_PROGRAM_ID,
+        futarchy_event_authority: futarchy_event_authority_pda(),
+        futarchy_program: FUTARCHY_PROGRAM_ID,
+        token_program: spl_token::ID.into(),
+        ata_token_program: spl_associated_token_account::ID.into(),
+        system_program: system_program::id(),
+    }
 }